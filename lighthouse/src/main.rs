@@ -0,0 +1,331 @@
+// SPDX-FileCopyrightText: 2026 Vladyslav Tsilytskyi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Rendezvous server of vpnparty. Buddies register here and learn the roster of their party.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use auth::{ReplayGuard, Secret};
+use ratelimit::TokenBuckets;
+use sup::{Gossip, Kind, Message, Settings};
+use vpnparty_protocol::{auth, logger, ratelimit, subnet, sup};
+use vpnparty_protocol::{debug, e, error, info, trace};
+
+const LIGHTHOUSE_PORT: u16 = 54930;
+
+/// Buddies register as often as they send heartbeats, silent ones are forgotten
+const REGISTRATION_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Registrations remembered at once, so spoofed ones can't exhaust memory
+const MAX_REGISTRATIONS: usize = 4096;
+
+const HELP: &str = "\
+lighthouse helps vpnparty buddies to find each other on large VPNs.
+
+USAGE:
+  lighthouse [FLAGS] [OPTIONS]
+
+FLAGS:
+  -h, --help              Prints help information
+  -V, --version           Prints application and Sup protocol versions
+
+OPTIONS:
+  -v, --verbose  NUMBER        Verbosity level [0-2] where 1 is debug and 2 trace level.
+  --listen       IP:PORT       Address to wait for registrations on, default is 0.0.0.0:54930.
+  --secret       \"SECRET\"      Accept only buddies with the same party secret.
+                               Without it anybody, who reaches the lighthouse, can register, even from a spoofed address.
+";
+
+#[derive(Debug)]
+struct Arguments {
+    listen: SocketAddr,
+    secret: Option<Secret>,
+}
+
+fn parse_args() -> Result<Arguments, String> {
+    use lexopt::prelude::*;
+
+    let max_verbosity = 3u8;
+
+    let mut listen: SocketAddr =
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), LIGHTHOUSE_PORT);
+    let mut secret: Option<Secret> = None;
+
+    let mut parser = lexopt::Parser::from_env();
+    while let Some(arg) = e!(parser.next()) {
+        match arg {
+            Short('v') | Long("verbose") => {
+                let verbosity: u8 = e!(e!(parser.value()).parse::<u8>());
+                logger::set_verbosity(std::cmp::min(verbosity, max_verbosity));
+            }
+            Long("listen") => {
+                let s: String = e!(e!(parser.value()).string());
+                listen = match IpAddr::from_str(&s) {
+                    Ok(ip) => SocketAddr::new(ip, LIGHTHOUSE_PORT),
+                    Err(_) => e!(SocketAddr::from_str(&s)),
+                };
+            }
+            Long("secret") => {
+                secret = Some(Secret::new(&e!(e!(parser.value()).string()))?);
+            }
+            Short('V') | Long("version") => {
+                println!(
+                    "lighthouse {}, Sup protocol v{}",
+                    env!("CARGO_PKG_VERSION"),
+                    sup::PROTOCOL_VERSION
+                );
+                std::process::exit(0);
+            }
+            Short('h') | Long("help") => {
+                println!("{}", HELP);
+                std::process::exit(0);
+            }
+            _ => return Err(format!("Unexpected command line option {:?}.", arg)),
+        }
+    }
+
+    Ok(Arguments { listen, secret })
+}
+
+/// Registered buddies of every party
+#[derive(Debug, Default)]
+struct Roster {
+    buddies: HashMap<(Option<String>, IpAddr), Instant>,
+}
+
+impl Roster {
+    /// Remember the buddy and tell which party members from its network are around.
+    /// Buddy outside of its network or new buddy, when roster is full, is refused.
+    fn register(
+        &mut self,
+        party: &Option<String>,
+        net: &subnet::IpNet,
+        ip: IpAddr,
+        now: Instant,
+    ) -> Result<Vec<IpAddr>, String> {
+        if !net.contains(&ip) {
+            return Err(format!("{} doesn't belong to {}", ip, net));
+        }
+        self.buddies
+            .retain(|_, seen| now.saturating_duration_since(*seen) <= REGISTRATION_TIMEOUT);
+        let key: (Option<String>, IpAddr) = (party.clone(), ip);
+        if !self.buddies.contains_key(&key) {
+            if self.buddies.len() >= MAX_REGISTRATIONS {
+                return Err(format!("roster is full, {} can't join", ip));
+            }
+            info!("{} joined the {}.", ip, sup::party_name(party));
+        }
+        self.buddies.insert(key, now);
+        Ok(self
+            .buddies
+            .keys()
+            .filter(|(p, b)| p == party && *b != ip && net.contains(b))
+            .map(|(_, b)| *b)
+            .take(sup::MAX_GOSSIP_PEERS)
+            .collect())
+    }
+}
+
+/// Roster with as many buddies as fit into the size of registration, so lighthouse can't amplify spoofed traffic
+fn fit_roster(
+    net: subnet::IpNet,
+    mut peers: Vec<IpAddr>,
+    request_len: usize,
+    sender_ip: IpAddr,
+    settings: &Settings,
+) -> Option<Vec<u8>> {
+    loop {
+        let reply: Message = Message {
+            gossip: Some(Gossip {
+                net,
+                peers: peers.clone(),
+            }),
+            ..Message::new(Kind::Roster)
+        };
+        let buf: Vec<u8> = reply.encode_for(sender_ip, settings);
+        if buf.len() <= request_len {
+            trace!("Telling {} buddies {:?}", peers.len(), peers);
+            return Some(buf);
+        }
+        peers.pop()?;
+    }
+}
+
+/// Signed roster must come from the address buddy has sent registration to
+fn reply_ip(socket: &UdpSocket, remote: SocketAddr) -> Result<IpAddr, String> {
+    let local: IpAddr = e!(socket.local_addr()).ip();
+    if !local.is_unspecified() {
+        return Ok(local);
+    }
+    // Ask the routing table, which address reaches the buddy
+    let probe: UdpSocket = e!(UdpSocket::bind(SocketAddr::new(local, 0)));
+    e!(probe.connect(remote));
+    Ok(e!(probe.local_addr()).ip())
+}
+
+fn main() -> Result<(), String> {
+    let args: Arguments = parse_args()?;
+    debug!("{:?}", args);
+
+    let socket: UdpSocket = e!(UdpSocket::bind(args.listen));
+    info!("Lighthouse is listening on {}.", args.listen);
+
+    let settings: Settings = Settings {
+        secret: args.secret.clone(),
+        ..Default::default()
+    };
+    let mut replay: ReplayGuard = ReplayGuard::default();
+    let mut roster: Roster = Roster::default();
+    let mut limiter: TokenBuckets = TokenBuckets::default();
+    let mut buf = [0; sup::MAX_MESSAGE_LEN];
+
+    loop {
+        let (len, remote) = match socket.recv_from(&mut buf) {
+            Ok(p) => p,
+            Err(e) => {
+                // Windows reports ICMP port unreachable of previous send here
+                if e.kind() != std::io::ErrorKind::ConnectionReset {
+                    error!("Error while receiving registration: {}", e);
+                }
+                continue;
+            }
+        };
+
+        let msg: Message = match Message::decode(&buf[..len]) {
+            Ok(m) => m,
+            Err(e) => {
+                debug!("Skipping message from {}: {}", remote.ip(), e);
+                continue;
+            }
+        };
+        if let Err(e) = sup::authenticate(&settings, &mut replay, &msg, &buf[..len], remote.ip()) {
+            debug!("Rejected Sup message from {}: {}.", remote.ip(), e);
+            continue;
+        }
+        let net: subnet::IpNet = match (msg.kind, &msg.gossip) {
+            (Kind::Register, Some(g)) => g.net,
+            _ => {
+                trace!("Skipping {:?} from {}.", msg.kind, remote.ip());
+                continue;
+            }
+        };
+
+        let now: Instant = Instant::now();
+        let peers: Vec<IpAddr> = match roster.register(&msg.party, &net, remote.ip(), now) {
+            Ok(p) => p,
+            Err(e) => {
+                debug!("Skipping registration: {}.", e);
+                continue;
+            }
+        };
+        if !limiter.allow(remote.ip(), now) {
+            debug!("{} exceeded reply rate.", remote.ip());
+            continue;
+        }
+        // Roster is sent on behalf of the buddy's party
+        let party_settings: Settings = Settings {
+            party: msg.party.clone(),
+            ..settings.clone()
+        };
+        let sender_ip: IpAddr = match reply_ip(&socket, remote) {
+            Ok(ip) => ip,
+            Err(e) => {
+                error!("Can't answer {}: {}", remote.ip(), e);
+                continue;
+            }
+        };
+        let Some(reply) = fit_roster(net, peers, len, sender_ip, &party_settings) else {
+            debug!(
+                "Registration of {} is too short for the roster.",
+                remote.ip()
+            );
+            continue;
+        };
+        if let Err(e) = socket.send_to(&reply, remote) {
+            error!("Can't send roster to {}: {}", remote.ip(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roster() {
+        let net: subnet::IpNet = subnet::IpNet::from_str("100.64.0.0/10").unwrap();
+        let party: Option<String> = Some("wc3".to_string());
+        let a: IpAddr = [100, 64, 0, 1].into();
+        let b: IpAddr = [100, 100, 0, 2].into();
+        let t0 = Instant::now();
+        let mut r = Roster::default();
+
+        assert!(r.register(&party, &net, a, t0).unwrap().is_empty());
+        assert!(r.register(&None, &net, b, t0).unwrap().is_empty());
+        assert_eq!(r.register(&party, &net, b, t0).unwrap(), [a]);
+        assert!(r
+            .register(&party, &subnet::IpNet::host(b), b, t0)
+            .unwrap()
+            .is_empty());
+        assert!(r
+            .register(&party, &"10.0.0.0/8".parse().unwrap(), b, t0)
+            .is_err());
+
+        // Silent buddy is forgotten
+        let later = t0 + REGISTRATION_TIMEOUT + Duration::from_secs(1);
+        assert!(r.register(&party, &net, b, later).unwrap().is_empty());
+    }
+
+    #[test]
+    fn roster_limit() {
+        let net: subnet::IpNet = "10.0.0.0/8".parse().unwrap();
+        let t0 = Instant::now();
+        let mut r = Roster::default();
+        for i in 0..MAX_REGISTRATIONS as u32 {
+            let ip: IpAddr = Ipv4Addr::from((10u32 << 24) + i).into();
+            assert!(r.register(&None, &net, ip, t0).is_ok());
+        }
+        let newcomer: IpAddr = [10, 255, 0, 1].into();
+        assert!(r.register(&None, &net, newcomer, t0).is_err());
+        // Registered buddies still renew their registration
+        assert!(r.register(&None, &net, [10, 0, 0, 1].into(), t0).is_ok());
+
+        let later = t0 + REGISTRATION_TIMEOUT + Duration::from_secs(1);
+        assert!(r.register(&None, &net, newcomer, later).is_ok());
+    }
+
+    #[test]
+    fn roster_size() {
+        let net: subnet::IpNet = "10.0.0.0/16".parse().unwrap();
+        let sender: IpAddr = [10, 0, 0, 1].into();
+        let peers: Vec<IpAddr> = (2..34u8).map(|i| IpAddr::from([10, 0, 0, i])).collect();
+        let settings = Settings::default();
+        let register = Message {
+            gossip: Some(Gossip {
+                net,
+                peers: Vec::new(),
+            }),
+            ..Message::new(Kind::Register)
+        };
+        let short: usize = register.encode().len();
+        let padded: usize = Message {
+            padding: 4 * sup::MAX_GOSSIP_PEERS,
+            ..register
+        }
+        .encode()
+        .len();
+
+        let full = fit_roster(net, peers.clone(), padded, sender, &settings).unwrap();
+        assert!(full.len() <= padded);
+        let decoded: Message = Message::decode(&full).unwrap();
+        assert_eq!(decoded.gossip.unwrap().peers, peers);
+
+        // Registration without padding gets a shorter roster
+        let cut = fit_roster(net, peers.clone(), short, sender, &settings).unwrap();
+        assert!(cut.len() <= short);
+        assert!(fit_roster(net, peers, 10, sender, &settings).is_none());
+    }
+}
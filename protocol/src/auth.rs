@@ -0,0 +1,144 @@
+// SPDX-FileCopyrightText: 2026 Vladyslav Tsilytskyi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Party secret, which authenticates Sup messages
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Length of HMAC-SHA256 tag
+pub const MAC_LEN: usize = 32;
+
+/// Buddies' clocks may differ this much, older messages are rejected
+pub const MAX_CLOCK_SKEW_SECS: u64 = 120;
+
+/// Pre-shared secret of the party. Never printed.
+#[derive(Clone)]
+pub struct Secret(Vec<u8>);
+
+impl Secret {
+    pub fn new(s: &str) -> Result<Secret, String> {
+        if s.is_empty() {
+            return Err("Empty party secret.".to_string());
+        }
+        Ok(Secret(s.as_bytes().to_vec()))
+    }
+
+    fn hmac(&self, sender: IpAddr, data: &[u8]) -> HmacSha256 {
+        // Any key length is fine for HMAC
+        let mut mac = HmacSha256::new_from_slice(&self.0).expect("HMAC accepts any key length");
+        match sender {
+            IpAddr::V4(ip) => mac.update(&ip.octets()),
+            IpAddr::V6(ip) => mac.update(&ip.octets()),
+        }
+        mac.update(data);
+        mac
+    }
+
+    /// Tag of the message sent from given address
+    pub fn sign(&self, sender: IpAddr, data: &[u8]) -> [u8; MAC_LEN] {
+        self.hmac(sender, data).finalize().into_bytes().into()
+    }
+
+    /// Constant time check of the tag
+    pub fn verify(&self, sender: IpAddr, data: &[u8], tag: &[u8]) -> bool {
+        self.hmac(sender, data).verify_slice(tag).is_ok()
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secret(***)")
+    }
+}
+
+/// Seconds since Unix epoch
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Random number of the OS generator, used for nonces and sender IDs.
+/// Time and process ID are the fallback, if the generator fails.
+pub fn random_u64() -> u64 {
+    let mut buf = [0u8; 8];
+    match getrandom::getrandom(&mut buf) {
+        Ok(()) => u64::from_ne_bytes(buf),
+        Err(_) => {
+            let nanos: u128 = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos())
+                .unwrap_or_default();
+            (nanos as u64) ^ ((std::process::id() as u64) << 32)
+        }
+    }
+}
+
+/// Rejects stale messages and messages seen before
+#[derive(Debug, Default)]
+pub struct ReplayGuard {
+    /// Sender ID and nonce with the message timestamp
+    seen: HashMap<(u64, u64), u64>,
+}
+
+impl ReplayGuard {
+    pub fn check(
+        &mut self,
+        sender: u64,
+        timestamp: u64,
+        nonce: u64,
+        now: u64,
+    ) -> Result<(), String> {
+        if timestamp.abs_diff(now) > MAX_CLOCK_SKEW_SECS {
+            return Err(format!(
+                "timestamp is {} seconds off, check the clock",
+                timestamp.abs_diff(now)
+            ));
+        }
+        // Stale entries can't pass the timestamp check anyway
+        self.seen
+            .retain(|_, t| t.abs_diff(now) <= MAX_CLOCK_SKEW_SECS);
+        if self.seen.insert((sender, nonce), timestamp).is_some() {
+            return Err("replayed message".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_and_verify() {
+        let s = Secret::new("lan party").unwrap();
+        let ip: IpAddr = [10, 0, 0, 1].into();
+        let tag = s.sign(ip, b"Sup");
+        assert!(s.verify(ip, b"Sup", &tag));
+        assert!(!s.verify([10, 0, 0, 2].into(), b"Sup", &tag));
+        assert!(!s.verify(ip, b"Sup!", &tag));
+        assert!(!Secret::new("other").unwrap().verify(ip, b"Sup", &tag));
+        assert!(Secret::new("").is_err());
+        assert_eq!(format!("{:?}", s), "Secret(***)");
+    }
+
+    #[test]
+    fn replay() {
+        let mut g = ReplayGuard::default();
+        let now: u64 = 1_000_000;
+        assert!(g.check(1, now, 7, now).is_ok());
+        assert!(g.check(1, now, 7, now + 1).is_err());
+        assert!(g.check(2, now, 7, now + 1).is_ok());
+        assert!(g.check(1, now - MAX_CLOCK_SKEW_SECS - 1, 8, now).is_err());
+        assert!(g.check(1, now + MAX_CLOCK_SKEW_SECS, 9, now).is_ok());
+    }
+}
@@ -0,0 +1,17 @@
+// SPDX-FileCopyrightText: 2026 Vladyslav Tsilytskyi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Sup protocol and helpers shared by vpnparty and lighthouse
+
+pub mod auth;
+pub mod logger;
+pub mod ratelimit;
+pub mod subnet;
+pub mod sup;
+
+/// Macro to cast any error type to String
+/// //TODO: verify
+#[macro_export]
+macro_rules! e {
+    ($($arg:tt)+) => ($($arg)+.map_err(|e| e.to_string())?)
+}
@@ -0,0 +1,112 @@
+// SPDX-FileCopyrightText: 2026 Vladyslav Tsilytskyi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Limit of replies sent to a single address, so discovery ports can't be used to spam someone
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Instant;
+
+/// Buddy gets a few replies at once, for hellos on several ports
+pub const REPLY_BURST: f64 = 8.0;
+
+/// Replies per second after the burst is spent
+pub const REPLY_RATE: f64 = 2.0;
+
+/// Senders tracked at once, full buckets are forgotten beyond this number
+const MAX_SOURCES: usize = 1024;
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token bucket for every source address
+#[derive(Debug)]
+pub struct TokenBuckets {
+    rate: f64,
+    burst: f64,
+    buckets: HashMap<IpAddr, Bucket>,
+}
+
+impl Default for TokenBuckets {
+    fn default() -> Self {
+        TokenBuckets::new(REPLY_RATE, REPLY_BURST)
+    }
+}
+
+impl TokenBuckets {
+    pub fn new(rate: f64, burst: f64) -> TokenBuckets {
+        TokenBuckets {
+            rate,
+            burst,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Take a token of the source, false if there are none left
+    pub fn allow(&mut self, ip: IpAddr, now: Instant) -> bool {
+        if self.buckets.len() >= MAX_SOURCES && !self.buckets.contains_key(&ip) {
+            self.forget_full(now);
+        }
+        let b: &mut Bucket = self.buckets.entry(ip).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        let elapsed: f64 = now.saturating_duration_since(b.updated).as_secs_f64();
+        b.tokens = f64::min(self.burst, b.tokens + elapsed * self.rate);
+        b.updated = now;
+        if b.tokens < 1.0 {
+            return false;
+        }
+        b.tokens -= 1.0;
+        true
+    }
+
+    /// Sources, which refilled their buckets, are the same as new ones
+    fn forget_full(&mut self, now: Instant) {
+        let (rate, burst) = (self.rate, self.burst);
+        self.buckets.retain(|_, b| {
+            b.tokens + now.saturating_duration_since(b.updated).as_secs_f64() * rate < burst
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    #[test]
+    fn burst_and_refill() {
+        let a: IpAddr = [10, 0, 0, 2].into();
+        let b: IpAddr = [10, 0, 0, 3].into();
+        let t0 = Instant::now();
+        let mut l = TokenBuckets::new(2.0, 3.0);
+        assert!((0..3).all(|_| l.allow(a, t0)));
+        assert!(!l.allow(a, t0));
+        // Other sources have their own buckets
+        assert!(l.allow(b, t0));
+
+        assert!(l.allow(a, t0 + Duration::from_millis(500)));
+        assert!(!l.allow(a, t0 + Duration::from_millis(500)));
+        // Bucket never holds more than the burst
+        let later = t0 + Duration::from_secs(60);
+        assert!((0..3).all(|_| l.allow(a, later)));
+        assert!(!l.allow(a, later));
+    }
+
+    #[test]
+    fn many_sources() {
+        let t0 = Instant::now();
+        let mut l = TokenBuckets::default();
+        for i in 0..MAX_SOURCES as u32 {
+            assert!(l.allow(IpAddr::V4(Ipv4Addr::from((10u32 << 24) + i)), t0));
+        }
+        let later = t0 + Duration::from_secs(60);
+        assert!(l.allow([192, 168, 0, 1].into(), later));
+        assert_eq!(l.buckets.len(), 1);
+    }
+}
@@ -0,0 +1,352 @@
+// SPDX-FileCopyrightText: 2026 Vladyslav Tsilytskyi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! IPv4 and IPv6 networks in CIDR notation
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+/// IPv4 network, host bits of the address are kept as is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4Net {
+    pub addr: Ipv4Addr,
+    pub prefix: u8,
+}
+
+impl Ipv4Net {
+    pub fn new(addr: Ipv4Addr, prefix: u8) -> Result<Ipv4Net, String> {
+        if prefix > 32 {
+            return Err(format!("Invalid network prefix {}.", prefix));
+        }
+        Ok(Ipv4Net { addr, prefix })
+    }
+
+    /// Build network from address and netmask like 255.255.240.0
+    pub fn from_netmask(addr: Ipv4Addr, netmask: Ipv4Addr) -> Result<Ipv4Net, String> {
+        let m: u32 = u32::from(netmask);
+        if m.leading_ones() + m.trailing_zeros() != 32 {
+            return Err(format!("Netmask {} is not contiguous.", netmask));
+        }
+        Ipv4Net::new(addr, m.leading_ones() as u8)
+    }
+
+    /// Smallest network that contains both addresses
+    pub fn covering(a: Ipv4Addr, b: Ipv4Addr) -> Ipv4Net {
+        let prefix = (u32::from(a) ^ u32::from(b)).leading_zeros() as u8;
+        Ipv4Net { addr: a, prefix }
+    }
+
+    pub fn netmask(&self) -> Ipv4Addr {
+        Ipv4Addr::from(self.mask())
+    }
+
+    fn mask(&self) -> u32 {
+        u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0)
+    }
+
+    pub fn network(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.addr) & self.mask())
+    }
+
+    pub fn broadcast(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.addr) | !self.mask())
+    }
+
+    pub fn contains(&self, ip: &Ipv4Addr) -> bool {
+        u32::from(*ip) & self.mask() == u32::from(self.addr) & self.mask()
+    }
+
+    /// Number of addresses available for hosts
+    pub fn host_count(&self) -> u64 {
+        match self.prefix {
+            32 => 1,
+            31 => 2,
+            p => (1u64 << (32 - p as u32)) - 2,
+        }
+    }
+
+    /// Addresses available for hosts, without network and broadcast addresses
+    pub fn hosts(&self) -> impl Iterator<Item = Ipv4Addr> {
+        let first: u32 = u32::from(self.network());
+        let last: u32 = u32::from(self.broadcast());
+        let (first, last) = if self.prefix >= 31 {
+            (first, last)
+        } else {
+            (first + 1, last - 1)
+        };
+        (first..=last).map(Ipv4Addr::from)
+    }
+}
+
+impl fmt::Display for Ipv4Net {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network(), self.prefix)
+    }
+}
+
+impl FromStr for Ipv4Net {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (ip, prefix) = s
+            .split_once('/')
+            .ok_or(format!("Network {} must be in CIDR notation.", s))?;
+        let addr: Ipv4Addr = Ipv4Addr::from_str(ip).map_err(|e| e.to_string())?;
+        let prefix: u8 = prefix.parse::<u8>().map_err(|e| e.to_string())?;
+        Ipv4Net::new(addr, prefix)
+    }
+}
+
+/// IPv6 network, host bits of the address are kept as is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv6Net {
+    pub addr: Ipv6Addr,
+    pub prefix: u8,
+}
+
+impl Ipv6Net {
+    pub fn new(addr: Ipv6Addr, prefix: u8) -> Result<Ipv6Net, String> {
+        if prefix > 128 {
+            return Err(format!("Invalid network prefix {}.", prefix));
+        }
+        Ok(Ipv6Net { addr, prefix })
+    }
+
+    /// Build network from address and netmask like ffff:ffff:ffff:ffff::
+    pub fn from_netmask(addr: Ipv6Addr, netmask: Ipv6Addr) -> Result<Ipv6Net, String> {
+        let m: u128 = u128::from(netmask);
+        if m.leading_ones() + m.trailing_zeros() != 128 {
+            return Err(format!("Netmask {} is not contiguous.", netmask));
+        }
+        Ipv6Net::new(addr, m.leading_ones() as u8)
+    }
+
+    pub fn netmask(&self) -> Ipv6Addr {
+        Ipv6Addr::from(self.mask())
+    }
+
+    fn mask(&self) -> u128 {
+        u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0)
+    }
+
+    pub fn network(&self) -> Ipv6Addr {
+        Ipv6Addr::from(u128::from(self.addr) & self.mask())
+    }
+
+    pub fn contains(&self, ip: &Ipv6Addr) -> bool {
+        u128::from(*ip) & self.mask() == u128::from(self.addr) & self.mask()
+    }
+
+    /// Number of addresses available for hosts, saturates for huge networks
+    pub fn host_count(&self) -> u64 {
+        match self.prefix {
+            128 => 1,
+            127 => 2,
+            p if p <= 64 => u64::MAX,
+            p => (1u64 << (128 - p as u32)) - 1,
+        }
+    }
+
+    /// Addresses available for hosts, without Subnet-Router anycast address
+    pub fn hosts(&self) -> impl Iterator<Item = Ipv6Addr> {
+        let first: u128 = u128::from(self.network());
+        let last: u128 = first | !self.mask();
+        let first: u128 = if self.prefix >= 127 { first } else { first + 1 };
+        (first..=last).map(Ipv6Addr::from)
+    }
+}
+
+impl fmt::Display for Ipv6Net {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network(), self.prefix)
+    }
+}
+
+impl FromStr for Ipv6Net {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (ip, prefix) = s
+            .split_once('/')
+            .ok_or(format!("Network {} must be in CIDR notation.", s))?;
+        let addr: Ipv6Addr = Ipv6Addr::from_str(ip).map_err(|e| e.to_string())?;
+        let prefix: u8 = prefix.parse::<u8>().map_err(|e| e.to_string())?;
+        Ipv6Net::new(addr, prefix)
+    }
+}
+
+/// Network of either family
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpNet {
+    V4(Ipv4Net),
+    V6(Ipv6Net),
+}
+
+impl IpNet {
+    /// Network of a single host
+    pub fn host(ip: IpAddr) -> IpNet {
+        match ip {
+            IpAddr::V4(a) => IpNet::V4(Ipv4Net {
+                addr: a,
+                prefix: 32,
+            }),
+            IpAddr::V6(a) => IpNet::V6(Ipv6Net {
+                addr: a,
+                prefix: 128,
+            }),
+        }
+    }
+
+    pub fn addr(&self) -> IpAddr {
+        match self {
+            IpNet::V4(n) => IpAddr::V4(n.addr),
+            IpNet::V6(n) => IpAddr::V6(n.addr),
+        }
+    }
+
+    pub fn prefix(&self) -> u8 {
+        match self {
+            IpNet::V4(n) => n.prefix,
+            IpNet::V6(n) => n.prefix,
+        }
+    }
+
+    pub fn netmask(&self) -> IpAddr {
+        match self {
+            IpNet::V4(n) => IpAddr::V4(n.netmask()),
+            IpNet::V6(n) => IpAddr::V6(n.netmask()),
+        }
+    }
+
+    pub fn host_count(&self) -> u64 {
+        match self {
+            IpNet::V4(n) => n.host_count(),
+            IpNet::V6(n) => n.host_count(),
+        }
+    }
+
+    pub fn hosts(&self) -> Box<dyn Iterator<Item = IpAddr> + Send> {
+        match self {
+            IpNet::V4(n) => Box::new(n.hosts().map(IpAddr::V4)),
+            IpNet::V6(n) => Box::new(n.hosts().map(IpAddr::V6)),
+        }
+    }
+
+    /// Addresses of other family never belong to the network
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self, ip) {
+            (IpNet::V4(n), IpAddr::V4(a)) => n.contains(a),
+            (IpNet::V6(n), IpAddr::V6(a)) => n.contains(a),
+            _ => false,
+        }
+    }
+
+    /// Whole other network lies inside this one
+    pub fn contains_net(&self, other: &IpNet) -> bool {
+        other.prefix() >= self.prefix() && self.contains(&other.addr())
+    }
+}
+
+impl fmt::Display for IpNet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpNet::V4(n) => n.fmt(f),
+            IpNet::V6(n) => n.fmt(f),
+        }
+    }
+}
+
+impl FromStr for IpNet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains(':') {
+            Ok(IpNet::V6(Ipv6Net::from_str(s)?))
+        } else {
+            Ok(IpNet::V4(Ipv4Net::from_str(s)?))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_display() {
+        let n: Ipv4Net = "100.101.102.103/10".parse().unwrap();
+        assert_eq!(n.to_string(), "100.64.0.0/10");
+        assert_eq!(n.netmask(), Ipv4Addr::new(255, 192, 0, 0));
+        assert_eq!(n.broadcast(), Ipv4Addr::new(100, 127, 255, 255));
+
+        assert!("10.0.0.0".parse::<Ipv4Net>().is_err());
+        assert!("10.0.0.0/33".parse::<Ipv4Net>().is_err());
+        assert!("10.0.0/8".parse::<Ipv4Net>().is_err());
+    }
+
+    #[test]
+    fn netmask() {
+        let ip = Ipv4Addr::new(10, 1, 2, 3);
+        let n = Ipv4Net::from_netmask(ip, Ipv4Addr::new(255, 255, 240, 0)).unwrap();
+        assert_eq!(n.prefix, 20);
+        assert!(n.contains(&Ipv4Addr::new(10, 1, 15, 200)));
+        assert!(!n.contains(&Ipv4Addr::new(10, 1, 16, 1)));
+        assert!(Ipv4Net::from_netmask(ip, Ipv4Addr::new(255, 0, 255, 0)).is_err());
+
+        let p2p = Ipv4Net::covering(Ipv4Addr::new(10, 8, 0, 6), Ipv4Addr::new(10, 8, 0, 5));
+        assert_eq!(p2p.prefix, 30);
+    }
+
+    #[test]
+    fn hosts() {
+        let n: Ipv4Net = "10.0.0.7/24".parse().unwrap();
+        assert_eq!(n.host_count(), 254);
+        let hosts: Vec<Ipv4Addr> = n.hosts().collect();
+        assert_eq!(hosts.len(), 254);
+        assert_eq!(hosts[0], Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(hosts[253], Ipv4Addr::new(10, 0, 0, 254));
+
+        let single: Ipv4Net = "10.0.0.7/32".parse().unwrap();
+        assert_eq!(
+            single.hosts().collect::<Vec<_>>(),
+            [Ipv4Addr::new(10, 0, 0, 7)]
+        );
+    }
+
+    #[test]
+    fn ipv6() {
+        let n: IpNet = "fd7a:115c:a1e0:ab12::5/48".parse().unwrap();
+        assert_eq!(n.to_string(), "fd7a:115c:a1e0::/48");
+        assert!(n.contains(&"fd7a:115c:a1e0:1::1".parse().unwrap()));
+        assert!(!n.contains(&"fd7a:115c:a1e1::1".parse().unwrap()));
+        assert!(!n.contains(&"10.0.0.1".parse().unwrap()));
+        assert_eq!(n.netmask().to_string(), "ffff:ffff:ffff::");
+
+        let m = Ipv6Net::from_netmask(
+            "fd00::1".parse().unwrap(),
+            "ffff:ffff:ffff:ffff::".parse().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(m.prefix, 64);
+
+        assert!("fd00::/129".parse::<IpNet>().is_err());
+        assert_eq!("10.0.0.0/8".parse::<IpNet>().unwrap().prefix(), 8);
+        assert_eq!(IpNet::host("fd00::1".parse().unwrap()).prefix(), 128);
+
+        let vpn: IpNet = "10.0.0.0/16".parse().unwrap();
+        assert!(vpn.contains_net(&"10.0.5.0/24".parse().unwrap()));
+        assert!(vpn.contains_net(&vpn));
+        assert!(!vpn.contains_net(&"10.0.0.0/8".parse().unwrap()));
+        assert!(!vpn.contains_net(&"10.1.0.0/24".parse().unwrap()));
+        assert!(!n.contains_net(&vpn));
+
+        let small: IpNet = "fd00::7/126".parse().unwrap();
+        assert_eq!(small.host_count(), 3);
+        let hosts: Vec<IpAddr> = small.hosts().collect();
+        assert_eq!(hosts.len(), 3);
+        assert_eq!(hosts[0], "fd00::5".parse::<IpAddr>().unwrap());
+        assert_eq!(hosts[2], "fd00::7".parse::<IpAddr>().unwrap());
+        assert_eq!(n.host_count(), u64::MAX);
+    }
+}
@@ -0,0 +1,1042 @@
+// SPDX-FileCopyrightText: 2026 Vladyslav Tsilytskyi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Sup protocol, which is used by vpnparty instances to find each other.
+//!
+//! Message layout:
+//! ```text
+//! 0      1         2           10
+//! | type | version | sender ID | field | field | ...
+//! ```
+//! Sender ID is big endian u64, random for every run.
+//! Every optional field is `| type: u8 | length: u16 BE | value |`, unknown fields are skipped.
+//! First version of the protocol used fixed 6 bytes hello and 10 bytes reply, they are still understood.
+//! Party name keeps several parties on the same VPN apart, default party has no name.
+//! Heartbeats carry known buddies of the VPN, so buddies learn each other via common ones.
+//! Heartbeats carry ping too, pong returns it back to measure round trip time of the VPN connection.
+//! Sender ID is the same on every VPN connection, so buddy reachable via several VPNs is recognized.
+//! Heartbeats carry tunnel port too, if the sender accepts forwarded packets via UDP tunnel.
+//! With party secret every message ends with HMAC field, which covers sender IP and all preceding bytes.
+//! Replies are never larger than requests, so discovery ports can't amplify spoofed traffic.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use crate::auth::{random_u64, unix_time, ReplayGuard, Secret, MAC_LEN};
+use crate::ratelimit::TokenBuckets;
+use crate::subnet::{IpNet, Ipv4Net, Ipv6Net};
+use crate::{debug, error, info, trace, warn};
+
+/// Version of this implementation. Version 1 is the legacy fixed hello.
+pub const PROTOCOL_VERSION: u8 = 2;
+
+/// Receive buffer size, messages never exceed it
+pub const MAX_MESSAGE_LEN: usize = 1400;
+
+/// "Sup!" of protocol version 1
+pub const LEGACY_SUP: [u8; 6] = [0x00, 0x01, 0x53, 0x75, 0x70, 0x21];
+/// "Sup, bro!" of protocol version 1
+pub const LEGACY_SUP_REPLY: [u8; 10] = [0x01, 0x53, 0x75, 0x70, 0x2c, 0x20, 0x62, 0x72, 0x6f, 0x21];
+
+const HEADER_LEN: usize = 10;
+const FIELD_HEADER_LEN: usize = 3;
+
+const FIELD_APP_VERSION: u8 = 0x01;
+const FIELD_TIMESTAMP: u8 = 0x02;
+const FIELD_NONCE: u8 = 0x03;
+/// Always the last field
+const FIELD_MAC: u8 = 0x04;
+const FIELD_PARTY: u8 = 0x05;
+const FIELD_PEERS: u8 = 0x06;
+/// Zeros, which make request large enough for the reply
+const FIELD_PADDING: u8 = 0x07;
+const FIELD_PING: u8 = 0x08;
+const FIELD_TUNNEL: u8 = 0x09;
+
+/// Longest party name in bytes
+pub const MAX_PARTY_LEN: usize = 64;
+
+/// Buddies in a single message, the rest is dropped
+pub const MAX_GOSSIP_PEERS: usize = 32;
+/// Gossiped addresses waiting for the answer
+const MAX_PROBES: usize = 256;
+/// Silent gossiped address is probed again after this time
+const PROBE_RETRY: Duration = Duration::from_secs(60);
+/// Legacy buddy is not poked anymore after this long silence
+const LEGACY_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Message types don't overlap with the first byte of legacy messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Hello,
+    Reply,
+    Heartbeat,
+    Bye,
+    /// Answer to hello from another party
+    WrongParty,
+    /// Registration at lighthouse, carries network of the sender
+    Register,
+    /// Lighthouse answer to registration, carries other buddies of the party
+    Roster,
+    /// Answer to heartbeat, carries its ping back
+    Pong,
+    /// Sent by newer protocol version
+    Unknown(u8),
+}
+
+impl Kind {
+    fn to_byte(self) -> u8 {
+        match self {
+            Kind::Hello => 0x10,
+            Kind::Reply => 0x11,
+            Kind::Heartbeat => 0x12,
+            Kind::Bye => 0x13,
+            Kind::WrongParty => 0x14,
+            Kind::Register => 0x15,
+            Kind::Roster => 0x16,
+            Kind::Pong => 0x17,
+            Kind::Unknown(b) => b,
+        }
+    }
+
+    fn from_byte(b: u8) -> Kind {
+        match b {
+            0x10 => Kind::Hello,
+            0x11 => Kind::Reply,
+            0x12 => Kind::Heartbeat,
+            0x13 => Kind::Bye,
+            0x14 => Kind::WrongParty,
+            0x15 => Kind::Register,
+            0x16 => Kind::Roster,
+            0x17 => Kind::Pong,
+            b => Kind::Unknown(b),
+        }
+    }
+}
+
+/// Buddies known to the sender on the VPN network
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gossip {
+    pub net: IpNet,
+    pub peers: Vec<IpAddr>,
+}
+
+impl Gossip {
+    /// Layout: `| family: 4 or 6 | prefix | network address | peer address | ... |`.
+    /// All addresses are of the same family.
+    fn encode(&self) -> Vec<u8> {
+        let mut buf: Vec<u8> = Vec::new();
+        let peers = self
+            .peers
+            .iter()
+            .filter(|p| p.is_ipv4() == self.net.addr().is_ipv4())
+            .take(MAX_GOSSIP_PEERS);
+        match self.net {
+            IpNet::V4(n) => {
+                buf.extend_from_slice(&[4, n.prefix]);
+                buf.extend_from_slice(&n.addr.octets());
+            }
+            IpNet::V6(n) => {
+                buf.extend_from_slice(&[6, n.prefix]);
+                buf.extend_from_slice(&n.addr.octets());
+            }
+        }
+        for p in peers {
+            match p {
+                IpAddr::V4(ip) => buf.extend_from_slice(&ip.octets()),
+                IpAddr::V6(ip) => buf.extend_from_slice(&ip.octets()),
+            }
+        }
+        buf
+    }
+
+    fn decode(value: &[u8]) -> Result<Gossip, String> {
+        let (family, prefix) = match value {
+            [f, p, ..] => (*f, *p),
+            _ => return Err("Sup peer list is too short.".to_string()),
+        };
+        let addr_len: usize = match family {
+            4 => 4,
+            6 => 16,
+            f => return Err(format!("Unknown address family {} in Sup peer list.", f)),
+        };
+        let chunks = value[2..].chunks_exact(addr_len);
+        if !chunks.remainder().is_empty() {
+            return Err("Sup peer list is truncated.".to_string());
+        }
+        let mut ips = chunks.map(|c| -> IpAddr {
+            match <[u8; 4]>::try_from(c) {
+                Ok(b) => IpAddr::V4(Ipv4Addr::from(b)),
+                Err(_) => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(c).unwrap_or_default())),
+            }
+        });
+        let net: IpNet = match ips.next() {
+            Some(IpAddr::V4(addr)) => IpNet::V4(Ipv4Net::new(addr, prefix)?),
+            Some(IpAddr::V6(addr)) => IpNet::V6(Ipv6Net::new(addr, prefix)?),
+            None => return Err("Sup peer list has no network.".to_string()),
+        };
+        Ok(Gossip {
+            net,
+            // Malicious buddy can't flood the roster
+            peers: ips.take(MAX_GOSSIP_PEERS).collect(),
+        })
+    }
+}
+
+/// Single Sup protocol message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub kind: Kind,
+    pub version: u8,
+    pub sender: u64,
+    pub app_version: Option<String>,
+    pub party: Option<String>,
+    pub gossip: Option<Gossip>,
+    /// Opaque value, which pong returns to the sender of heartbeat
+    pub ping: Option<u64>,
+    /// Port, which accepts forwarded packets via UDP tunnel
+    pub tunnel: Option<u16>,
+    /// Seconds since Unix epoch, only in signed messages
+    pub timestamp: Option<u64>,
+    pub nonce: Option<u64>,
+    /// Length of padding field
+    pub padding: usize,
+    pub signed: bool,
+}
+
+impl Message {
+    /// Message from this instance. Greetings carry application version.
+    pub fn new(kind: Kind) -> Message {
+        let app_version = match kind {
+            Kind::Hello | Kind::Reply => Some(env!("CARGO_PKG_VERSION").to_string()),
+            _ => None,
+        };
+        Message {
+            kind,
+            version: PROTOCOL_VERSION,
+            sender: local_id(),
+            app_version,
+            party: None,
+            gossip: None,
+            ping: None,
+            tunnel: None,
+            timestamp: None,
+            nonce: None,
+            padding: 0,
+            signed: false,
+        }
+    }
+
+    /// Encode message sent from given address with party name.
+    /// Sign it if party has a secret.
+    pub fn encode_for(&self, sender_ip: IpAddr, settings: &Settings) -> Vec<u8> {
+        let named = Message {
+            party: settings.party.clone(),
+            ..self.clone()
+        };
+        let Some(secret) = &settings.secret else {
+            return named.encode();
+        };
+        let fresh = Message {
+            timestamp: Some(unix_time()),
+            nonce: Some(random_u64()),
+            ..named
+        };
+        let mut buf: Vec<u8> = fresh.encode();
+        let tag: [u8; MAC_LEN] = secret.sign(sender_ip, &buf);
+        push_field(&mut buf, FIELD_MAC, &tag);
+        buf
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf: Vec<u8> = Vec::with_capacity(HEADER_LEN);
+        buf.push(self.kind.to_byte());
+        buf.push(self.version);
+        buf.extend_from_slice(&self.sender.to_be_bytes());
+        if let Some(v) = &self.app_version {
+            push_field(&mut buf, FIELD_APP_VERSION, v.as_bytes());
+        }
+        if let Some(p) = &self.party {
+            push_field(&mut buf, FIELD_PARTY, p.as_bytes());
+        }
+        if let Some(g) = &self.gossip {
+            push_field(&mut buf, FIELD_PEERS, &g.encode());
+        }
+        if let Some(p) = self.ping {
+            push_field(&mut buf, FIELD_PING, &p.to_be_bytes());
+        }
+        if let Some(p) = self.tunnel {
+            push_field(&mut buf, FIELD_TUNNEL, &p.to_be_bytes());
+        }
+        if let Some(t) = self.timestamp {
+            push_field(&mut buf, FIELD_TIMESTAMP, &t.to_be_bytes());
+        }
+        if let Some(n) = self.nonce {
+            push_field(&mut buf, FIELD_NONCE, &n.to_be_bytes());
+        }
+        if self.padding > 0 {
+            push_field(&mut buf, FIELD_PADDING, &vec![0; self.padding]);
+        }
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Message, String> {
+        if buf.len() < HEADER_LEN {
+            return Err(format!("Sup message is too short ({} bytes).", buf.len()));
+        }
+        let mut msg = Message {
+            kind: Kind::from_byte(buf[0]),
+            version: buf[1],
+            sender: u64::from_be_bytes(buf[2..HEADER_LEN].try_into().unwrap_or_default()),
+            app_version: None,
+            party: None,
+            gossip: None,
+            ping: None,
+            tunnel: None,
+            timestamp: None,
+            nonce: None,
+            padding: 0,
+            signed: false,
+        };
+
+        let mut rest: &[u8] = &buf[HEADER_LEN..];
+        while !rest.is_empty() {
+            if rest.len() < FIELD_HEADER_LEN {
+                return Err("Truncated Sup field header.".to_string());
+            }
+            let field: u8 = rest[0];
+            let len: usize = u16::from_be_bytes([rest[1], rest[2]]) as usize;
+            let value: &[u8] = rest
+                .get(FIELD_HEADER_LEN..FIELD_HEADER_LEN + len)
+                .ok_or(format!("Sup field {} is truncated.", field))?;
+            rest = &rest[FIELD_HEADER_LEN + len..];
+            match field {
+                FIELD_APP_VERSION => {
+                    msg.app_version = Some(String::from_utf8_lossy(value).into_owned())
+                }
+                FIELD_PARTY => msg.party = Some(String::from_utf8_lossy(value).into_owned()),
+                FIELD_PEERS => msg.gossip = Some(Gossip::decode(value)?),
+                FIELD_TIMESTAMP => msg.timestamp = Some(read_u64(value)?),
+                FIELD_NONCE => msg.nonce = Some(read_u64(value)?),
+                FIELD_PADDING => msg.padding = len,
+                FIELD_PING => msg.ping = Some(read_u64(value)?),
+                FIELD_TUNNEL => {
+                    let b: [u8; 2] = value
+                        .try_into()
+                        .map_err(|_| format!("Sup tunnel port of {} bytes.", len))?;
+                    msg.tunnel = Some(u16::from_be_bytes(b));
+                }
+                FIELD_MAC => {
+                    if len != MAC_LEN || !rest.is_empty() {
+                        return Err("Sup MAC must be the last field.".to_string());
+                    }
+                    msg.signed = true;
+                }
+                _ => {}
+            }
+        }
+        Ok(msg)
+    }
+}
+
+fn read_u64(value: &[u8]) -> Result<u64, String> {
+    let b: [u8; 8] = value
+        .try_into()
+        .map_err(|_| format!("Sup field of {} bytes is not a number.", value.len()))?;
+    Ok(u64::from_be_bytes(b))
+}
+
+/// Check HMAC, which is the last field of the message
+fn verify_mac(buf: &[u8], sender_ip: IpAddr, secret: &Secret) -> Result<(), String> {
+    let signed_len: usize = buf.len().saturating_sub(FIELD_HEADER_LEN + MAC_LEN);
+    if !secret.verify(
+        sender_ip,
+        &buf[..signed_len],
+        &buf[signed_len + FIELD_HEADER_LEN..],
+    ) {
+        return Err("wrong MAC, different secret".to_string());
+    }
+    Ok(())
+}
+
+fn push_field(buf: &mut Vec<u8>, field: u8, value: &[u8]) {
+    buf.push(field);
+    buf.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    buf.extend_from_slice(value);
+}
+
+/// Human readable party name for messages
+pub fn party_name(party: &Option<String>) -> String {
+    match party {
+        Some(p) => format!("party \"{}\"", p),
+        None => "default party".to_string(),
+    }
+}
+
+/// Random ID of this instance, which tells own messages from buddies' ones
+pub fn local_id() -> u64 {
+    static ID: OnceLock<u64> = OnceLock::new();
+    *ID.get_or_init(random_u64)
+}
+
+/// Settings shared by discovery of every direction
+#[derive(Debug, Clone, Default)]
+pub struct Settings {
+    /// Base interval of repeated discovery
+    pub refresh: Duration,
+    pub secret: Option<Secret>,
+    /// Only buddies with the same party name are accepted
+    pub party: Option<String>,
+    /// Rendezvous server, which knows every buddy of the party
+    pub lighthouse: Option<SocketAddr>,
+    /// Pace of UDP discovery sweeps, zero means no limit
+    pub pps: u32,
+}
+
+impl Settings {
+    /// Legacy messages carry no secret and no party name
+    fn allows_legacy(&self) -> bool {
+        self.secret.is_none() && self.party.is_none()
+    }
+}
+
+/// Address of the socket is the sender address of signed messages
+fn local_ip(socket: &UdpSocket) -> Result<IpAddr, String> {
+    socket
+        .local_addr()
+        .map(|a| a.ip())
+        .map_err(|e| e.to_string())
+}
+
+/// Greet buddy or multicast group. Legacy buddies are found by their own hello
+/// and greeted with legacy one afterwards, see Responder::poke_legacy.
+pub fn send_hello(socket: &UdpSocket, dst: SocketAddr, settings: &Settings) -> Result<(), String> {
+    let hello: Vec<u8> = Message::new(Kind::Hello).encode_for(local_ip(socket)?, settings);
+    socket.send_to(&hello, dst).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Both sides must have the same secret or both must have none
+pub fn authenticate(
+    settings: &Settings,
+    replay: &mut ReplayGuard,
+    msg: &Message,
+    buf: &[u8],
+    sender_ip: IpAddr,
+) -> Result<(), String> {
+    let Some(secret) = &settings.secret else {
+        if msg.signed {
+            return Err("message is signed, but --secret is not given".to_string());
+        }
+        return Ok(());
+    };
+    if !msg.signed {
+        return Err("message is not signed".to_string());
+    }
+    verify_mac(buf, sender_ip, secret)?;
+    match (msg.timestamp, msg.nonce) {
+        (Some(t), Some(n)) => replay.check(msg.sender, t, n, unix_time()),
+        _ => Err("signed message without timestamp or nonce".to_string()),
+    }
+}
+
+/// What buddy told about itself
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    pub id: u64,
+    /// Port, which accepts forwarded packets via UDP tunnel
+    pub tunnel: Option<u16>,
+    /// Buddies, which the sender talks to directly. Only heartbeats tell them.
+    pub reaches: Option<Vec<IpAddr>>,
+    /// Status comes from heartbeat, so missing tunnel port means there is no tunnel
+    pub heartbeat: bool,
+}
+
+impl Status {
+    fn of(msg: &Message) -> Status {
+        Status {
+            id: msg.sender,
+            tunnel: msg.tunnel,
+            reaches: None,
+            heartbeat: msg.kind == Kind::Heartbeat,
+        }
+    }
+}
+
+/// What discovery should do with the sender of received message.
+/// Status is known for every buddy, except legacy ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Received {
+    Alive(Option<Status>),
+    Bye(Option<u64>),
+    Ignored,
+}
+
+/// Messages, which were dropped instead of being answered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Dropped {
+    /// Sender is outside of VPN network
+    pub outsiders: u64,
+    /// Sender exceeded its reply rate
+    pub limited: u64,
+    /// Reply would be larger than the request
+    pub oversized: u64,
+    /// Malformed, unauthenticated or replayed messages
+    pub rejected: u64,
+}
+
+impl fmt::Display for Dropped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} from outside of VPN network, {} rate limited, {} oversized, {} rejected",
+            self.outsiders, self.limited, self.oversized, self.rejected
+        )
+    }
+}
+
+/// Answers received messages and remembers protocol version of every buddy
+#[derive(Debug, Default)]
+pub struct Responder {
+    versions: HashMap<IpAddr, u8>,
+    /// Buddies with protocol version 1, which don't send heartbeats, with the time they were heard
+    legacy: HashMap<IpAddr, (SocketAddr, Instant)>,
+    /// Instances of other parties nearby, with their party names
+    strangers: HashMap<IpAddr, Option<String>>,
+    /// Gossiped addresses, which were greeted, but didn't answer yet
+    probes: HashMap<IpAddr, Instant>,
+    /// Port to greet gossiped buddies at, own port if not set
+    probe_port: Option<u16>,
+    /// Only buddies of this network are answered
+    net: Option<IpNet>,
+    settings: Settings,
+    replay: ReplayGuard,
+    limiter: TokenBuckets,
+    dropped: Dropped,
+}
+
+impl Responder {
+    pub fn new(settings: Settings) -> Responder {
+        Responder {
+            settings,
+            ..Default::default()
+        }
+    }
+
+    /// Socket of ephemeral port greets buddies at their discovery port
+    pub fn with_probe_port(self, port: u16) -> Responder {
+        Responder {
+            probe_port: Some(port),
+            ..self
+        }
+    }
+
+    /// Sup from other addresses is dropped, only lighthouse may be outside of the network
+    pub fn with_network(self, net: IpNet) -> Responder {
+        Responder {
+            net: Some(net),
+            ..self
+        }
+    }
+
+    pub fn dropped(&self) -> Dropped {
+        self.dropped
+    }
+
+    fn is_neighbour(&self, ip: &IpAddr) -> bool {
+        match &self.net {
+            Some(n) => n.contains(ip),
+            None => true,
+        }
+    }
+
+    pub fn handle(&mut self, socket: &UdpSocket, remote: SocketAddr, buf: &[u8]) -> Received {
+        let buddy_ip: IpAddr = remote.ip();
+
+        if !self.is_neighbour(&buddy_ip) && Some(remote) != self.settings.lighthouse {
+            trace!(
+                "Dropped Sup from {}, it is outside of VPN network.",
+                buddy_ip
+            );
+            self.dropped.outsiders += 1;
+            return Received::Ignored;
+        }
+
+        if buf == LEGACY_SUP || buf == LEGACY_SUP_REPLY {
+            if !self.settings.allows_legacy() {
+                debug!("Rejected legacy Sup from {}.", buddy_ip);
+                self.dropped.rejected += 1;
+                return Received::Ignored;
+            }
+            // Earlier releases of newer buddies send legacy hello along with the current one
+            if self.versions.get(&buddy_ip).is_some_and(|v| *v > 1) {
+                return Received::Ignored;
+            }
+            self.note_version(buddy_ip, 1);
+            // Legacy reply is larger than hello, so legacy buddy is poked with hello instead
+            self.legacy.insert(buddy_ip, (remote, Instant::now()));
+            return Received::Alive(None);
+        }
+
+        let msg: Message = match Message::decode(buf) {
+            Ok(m) => m,
+            Err(e) => {
+                debug!("Skipping message from {}: {}", buddy_ip, e);
+                self.dropped.rejected += 1;
+                return Received::Ignored;
+            }
+        };
+        if msg.sender == local_id() {
+            return Received::Ignored;
+        }
+        if let Err(e) = self.authenticate(&msg, buf, buddy_ip) {
+            debug!("Rejected Sup message from {}: {}.", buddy_ip, e);
+            self.dropped.rejected += 1;
+            return Received::Ignored;
+        }
+        self.note_version(buddy_ip, msg.version);
+        self.legacy.remove(&buddy_ip);
+
+        if msg.party != self.settings.party || msg.kind == Kind::WrongParty {
+            self.note_stranger(buddy_ip, &msg.party);
+            if msg.kind == Kind::Hello {
+                // Let them know we are here, but not in their party
+                self.answer(socket, remote, Message::new(Kind::WrongParty), buf.len());
+            }
+            return Received::Ignored;
+        }
+        self.strangers.remove(&buddy_ip);
+
+        match msg.kind {
+            Kind::Hello => {
+                if let Some(v) = &msg.app_version {
+                    debug!("{} runs vpnparty {}.", buddy_ip, v);
+                }
+                self.answer(socket, remote, Message::new(Kind::Reply), buf.len());
+                Received::Alive(Some(Status::of(&msg)))
+            }
+            Kind::Heartbeat => {
+                let mut status: Status = Status::of(&msg);
+                if let Some(g) = &msg.gossip {
+                    // Truncated list doesn't tell, whom the buddy can't reach
+                    if self.probe_gossip(socket, buddy_ip, g) && g.peers.len() < MAX_GOSSIP_PEERS {
+                        status.reaches = Some(g.peers.clone());
+                    }
+                }
+                if msg.ping.is_some() {
+                    let pong = Message {
+                        ping: msg.ping,
+                        ..Message::new(Kind::Pong)
+                    };
+                    self.answer(socket, remote, pong, buf.len());
+                }
+                Received::Alive(Some(status))
+            }
+            Kind::Reply | Kind::Pong | Kind::Unknown(_) => Received::Alive(Some(Status::of(&msg))),
+            Kind::Bye => Received::Bye(Some(msg.sender)),
+            Kind::Roster => {
+                match &msg.gossip {
+                    Some(g) if Some(remote) == self.settings.lighthouse => {
+                        self.probe(socket, buddy_ip, &g.peers)
+                    }
+                    _ => debug!("Skipping roster from {}, it is not our lighthouse.", remote),
+                }
+                Received::Ignored
+            }
+            Kind::WrongParty | Kind::Register => Received::Ignored,
+        }
+    }
+
+    /// Reply is limited by rate of the sender and size of the request
+    fn answer(
+        &mut self,
+        socket: &UdpSocket,
+        remote: SocketAddr,
+        reply: Message,
+        request_len: usize,
+    ) {
+        let ip: IpAddr = match local_ip(socket) {
+            Ok(ip) => ip,
+            Err(e) => {
+                error!("Greeting the {} buddy failed: {}", remote.ip(), e);
+                return;
+            }
+        };
+        let mut reply: Message = reply;
+        let mut msg: Vec<u8> = reply.encode_for(ip, &self.settings);
+        if msg.len() > request_len {
+            // Buddy with shorter version string still gets the reply
+            reply.app_version = None;
+            msg = reply.encode_for(ip, &self.settings);
+        }
+        if msg.len() > request_len {
+            trace!("Reply to {} is larger than its request.", remote.ip());
+            self.dropped.oversized += 1;
+            return;
+        }
+        if !self.limiter.allow(remote.ip(), Instant::now()) {
+            trace!("{} exceeded reply rate.", remote.ip());
+            self.dropped.limited += 1;
+            return;
+        }
+        self.reply(socket, remote, &msg);
+    }
+
+    /// Greet unknown buddies of the buddy. They become buddies only when they answer.
+    /// False if gossip can't be trusted.
+    fn probe_gossip(&mut self, socket: &UdpSocket, buddy_ip: IpAddr, gossip: &Gossip) -> bool {
+        if !gossip.net.contains(&buddy_ip) {
+            debug!(
+                "{} gossips about {}, which it doesn't belong to.",
+                buddy_ip, gossip.net
+            );
+            return false;
+        }
+        self.probe(socket, buddy_ip, &gossip.peers);
+        true
+    }
+
+    /// Greet buddies, which were reported by buddy or lighthouse
+    fn probe(&mut self, socket: &UdpSocket, source: IpAddr, peers: &[IpAddr]) {
+        let local: SocketAddr = match socket.local_addr() {
+            Ok(a) => a,
+            Err(e) => {
+                error!("Can't probe buddies of {}: {}", source, e);
+                return;
+            }
+        };
+        // Everybody in the party listens on the same port
+        let port: u16 = self.probe_port.unwrap_or(local.port());
+        let now: Instant = Instant::now();
+        self.probes
+            .retain(|_, t| now.saturating_duration_since(*t) < PROBE_RETRY);
+
+        for ip in peers {
+            if *ip == local.ip()
+                || ip.is_ipv4() != local.is_ipv4()
+                || ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                || !self.is_neighbour(ip)
+                || self.versions.contains_key(ip)
+                || self.probes.contains_key(ip)
+            {
+                continue;
+            }
+            if self.probes.len() >= MAX_PROBES {
+                debug!("Too many buddies to probe, skipping gossip of {}.", source);
+                break;
+            }
+            debug!("{} knows {}, probing it.", source, ip);
+            self.probes.insert(*ip, now);
+            if let Err(e) = send_hello(socket, SocketAddr::new(*ip, port), &self.settings) {
+                debug!("Probing {} failed: {}", ip, e);
+            }
+        }
+    }
+
+    /// Report once every instance of another party
+    fn note_stranger(&mut self, ip: IpAddr, party: &Option<String>) {
+        if self.strangers.get(&ip) == Some(party) {
+            return;
+        }
+        self.strangers.insert(ip, party.clone());
+        info!("{} runs vpnparty nearby, but in {}.", ip, party_name(party));
+    }
+
+    fn authenticate(&mut self, msg: &Message, buf: &[u8], buddy_ip: IpAddr) -> Result<(), String> {
+        authenticate(&self.settings, &mut self.replay, msg, buf, buddy_ip)
+    }
+
+    /// Legacy buddies answer hello only, so they are greeted instead of heartbeats
+    pub fn poke_legacy(&mut self, socket: &UdpSocket) {
+        let now: Instant = Instant::now();
+        self.legacy
+            .retain(|_, (_, seen)| now.saturating_duration_since(*seen) < LEGACY_TIMEOUT);
+        for (addr, _) in self.legacy.values() {
+            if let Err(e) = socket.send_to(&LEGACY_SUP, addr) {
+                error!("Can't reach {} buddy: {}", addr.ip(), e);
+            }
+        }
+    }
+
+    fn reply(&self, socket: &UdpSocket, remote: SocketAddr, msg: &[u8]) {
+        // Greetings to the newcommer.
+        if let Err(e) = socket.send_to(msg, remote) {
+            error!("Greeting the {} buddy failed: {}", remote.ip(), e);
+        }
+    }
+
+    /// Warn once when buddy speaks another protocol version
+    fn note_version(&mut self, ip: IpAddr, version: u8) {
+        if self.versions.insert(ip, version) == Some(version) {
+            return;
+        }
+        if version > PROTOCOL_VERSION {
+            warn!(
+                "{} speaks newer Sup protocol v{} (ours is v{}). Consider updating vpnparty.",
+                ip, version, PROTOCOL_VERSION
+            );
+        } else if version < PROTOCOL_VERSION {
+            warn!(
+                "{} runs older vpnparty with Sup protocol v{} (ours is v{}). Ask your buddy to update.",
+                ip, version, PROTOCOL_VERSION
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let hello = Message::new(Kind::Hello);
+        let buf: Vec<u8> = hello.encode();
+        assert_eq!(buf[0], 0x10);
+        assert_eq!(buf[1], PROTOCOL_VERSION);
+        assert_eq!(Message::decode(&buf), Ok(hello));
+
+        let hb = Message::new(Kind::Heartbeat);
+        assert_eq!(hb.encode().len(), HEADER_LEN);
+        assert_eq!(Message::decode(&hb.encode()), Ok(hb));
+
+        let tunneled = Message {
+            tunnel: Some(54932),
+            ..Message::new(Kind::Heartbeat)
+        };
+        assert_eq!(Message::decode(&tunneled.encode()), Ok(tunneled));
+    }
+
+    #[test]
+    fn extensibility() {
+        // Newer peer: unknown type and unknown field
+        let mut buf: Vec<u8> = vec![0x42, 7, 0, 0, 0, 0, 0, 0, 0, 9];
+        push_field(&mut buf, 0xEE, &[1, 2, 3]);
+        push_field(&mut buf, FIELD_APP_VERSION, b"9.0.0");
+        let msg: Message = Message::decode(&buf).unwrap();
+        assert_eq!(msg.kind, Kind::Unknown(0x42));
+        assert_eq!(msg.version, 7);
+        assert_eq!(msg.sender, 9);
+        assert_eq!(msg.app_version.as_deref(), Some("9.0.0"));
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(Message::decode(&LEGACY_SUP).is_err());
+        let mut buf: Vec<u8> = Message::new(Kind::Reply).encode();
+        buf.truncate(buf.len() - 1);
+        assert!(Message::decode(&buf).is_err());
+        buf.truncate(HEADER_LEN + 1);
+        assert!(Message::decode(&buf).is_err());
+    }
+
+    #[test]
+    fn signed() {
+        let settings = Settings {
+            secret: Some(Secret::new("lan party").unwrap()),
+            ..Default::default()
+        };
+        let ip: IpAddr = [10, 0, 0, 1].into();
+        let buf: Vec<u8> = Message::new(Kind::Hello).encode_for(ip, &settings);
+        let msg: Message = Message::decode(&buf).unwrap();
+        assert!(msg.signed);
+        assert!(msg.timestamp.is_some() && msg.nonce.is_some());
+
+        let mut r = Responder::new(settings.clone());
+        // Own ID is skipped by handle(), so check authentication directly
+        assert_eq!(r.authenticate(&msg, &buf, ip), Ok(()));
+        assert!(r.authenticate(&msg, &buf, ip).is_err());
+        let other = Message::decode(&Message::new(Kind::Hello).encode_for(ip, &settings));
+        assert!(r
+            .authenticate(&other.unwrap(), &buf, [10, 0, 0, 2].into())
+            .is_err());
+
+        let unsigned: Vec<u8> = Message::new(Kind::Hello).encode();
+        let plain: Message = Message::decode(&unsigned).unwrap();
+        assert!(r.authenticate(&plain, &unsigned, ip).is_err());
+        assert!(Responder::default().authenticate(&msg, &buf, ip).is_err());
+        assert_eq!(
+            Responder::default().authenticate(&plain, &unsigned, ip),
+            Ok(())
+        );
+
+        // MAC in the middle
+        let mut moved: Vec<u8> = buf.clone();
+        push_field(&mut moved, FIELD_APP_VERSION, b"1");
+        assert!(Message::decode(&moved).is_err());
+    }
+
+    #[test]
+    fn party() {
+        let settings = Settings {
+            party: Some("Blue team".to_string()),
+            ..Default::default()
+        };
+        let ip: IpAddr = [10, 0, 0, 1].into();
+        let msg: Message =
+            Message::decode(&Message::new(Kind::Hello).encode_for(ip, &settings)).unwrap();
+        assert_eq!(msg.party.as_deref(), Some("Blue team"));
+        assert!(!settings.allows_legacy());
+        assert!(Settings::default().allows_legacy());
+        assert_eq!(party_name(&msg.party), "party \"Blue team\"");
+        assert_eq!(party_name(&None), "default party");
+    }
+
+    #[test]
+    fn gossip() {
+        let g = Gossip {
+            net: "10.0.0.0/16".parse().unwrap(),
+            peers: (1..=40u8).map(|i| IpAddr::from([10, 0, 1, i])).collect(),
+        };
+        let mut hb = Message::new(Kind::Heartbeat);
+        hb.gossip = Some(g.clone());
+        let decoded: Message = Message::decode(&hb.encode()).unwrap();
+        let dg: Gossip = decoded.gossip.unwrap();
+        assert_eq!(dg.net, g.net);
+        assert_eq!(dg.peers.len(), MAX_GOSSIP_PEERS);
+        assert_eq!(dg.peers[..], g.peers[..MAX_GOSSIP_PEERS]);
+
+        let g6 = Gossip {
+            net: "fd00::/64".parse().unwrap(),
+            peers: vec!["fd00::2".parse().unwrap(), [10, 0, 0, 1].into()],
+        };
+        let dg6: Gossip = Gossip::decode(&g6.encode()).unwrap();
+        assert_eq!(dg6.peers, ["fd00::2".parse::<IpAddr>().unwrap()]);
+
+        assert!(Gossip::decode(&[4, 16, 10, 0, 0]).is_err());
+        assert!(Gossip::decode(&[5, 16, 10, 0, 0, 0]).is_err());
+        assert!(Gossip::decode(&[4, 33, 10, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn anti_amplification() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let buddy = UdpSocket::bind("127.0.0.1:0").unwrap();
+        buddy
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        let remote: SocketAddr = buddy.local_addr().unwrap();
+        let mut r =
+            Responder::new(Settings::default()).with_network("127.0.0.0/8".parse().unwrap());
+        let mut buf = [0; MAX_MESSAGE_LEN];
+
+        let seven = Status {
+            id: 7,
+            tunnel: None,
+            reaches: None,
+            heartbeat: false,
+        };
+        let outsider: SocketAddr = "10.0.0.2:54928".parse().unwrap();
+        let hello: Vec<u8> = Message {
+            sender: 7,
+            ..Message::new(Kind::Hello)
+        }
+        .encode();
+        assert_eq!(r.handle(&socket, outsider, &hello), Received::Ignored);
+        assert_eq!(r.dropped().outsiders, 1);
+
+        assert_eq!(
+            r.handle(&socket, remote, &hello),
+            Received::Alive(Some(seven.clone()))
+        );
+        assert!(buddy.recv(&mut buf).unwrap() <= hello.len());
+
+        // Reply to a hello without version is stripped of version too
+        let bare: Vec<u8> = Message {
+            sender: 7,
+            app_version: None,
+            ..Message::new(Kind::Hello)
+        }
+        .encode();
+        assert_eq!(
+            r.handle(&socket, remote, &bare),
+            Received::Alive(Some(seven.clone()))
+        );
+        assert_eq!(buddy.recv(&mut buf).unwrap(), bare.len());
+        let tiny: Vec<u8> = bare[..HEADER_LEN].to_vec();
+        r.handle(&socket, remote, &tiny);
+        assert_eq!(r.dropped().oversized, 0);
+
+        for _ in 0..20 {
+            r.handle(&socket, remote, &hello);
+        }
+        assert!(r.dropped().limited > 0);
+
+        // Legacy hello is not answered with larger legacy reply
+        let mut legacy = Responder::new(Settings::default());
+        let quiet = UdpSocket::bind("127.0.0.1:0").unwrap();
+        quiet
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        let quiet_addr: SocketAddr = quiet.local_addr().unwrap();
+        assert_eq!(
+            legacy.handle(&socket, quiet_addr, &LEGACY_SUP),
+            Received::Alive(None)
+        );
+        assert!(quiet.recv(&mut buf).is_err());
+
+        // Known legacy buddy is greeted with legacy hello, which it answers
+        legacy.poke_legacy(&socket);
+        assert_eq!(quiet.recv(&mut buf).unwrap(), LEGACY_SUP.len());
+        assert_eq!(buf[..LEGACY_SUP.len()], LEGACY_SUP);
+    }
+
+    #[test]
+    fn status() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let remote: SocketAddr = "127.0.0.2:54928".parse().unwrap();
+        let mut r = Responder::new(Settings::default());
+        let heartbeat = Message {
+            sender: 7,
+            tunnel: Some(54932),
+            gossip: Some(Gossip {
+                net: "127.0.0.0/8".parse().unwrap(),
+                peers: vec![[127, 0, 0, 3].into()],
+            }),
+            ..Message::new(Kind::Heartbeat)
+        };
+        assert_eq!(
+            r.handle(&socket, remote, &heartbeat.encode()),
+            Received::Alive(Some(Status {
+                id: 7,
+                tunnel: Some(54932),
+                reaches: Some(vec![[127, 0, 0, 3].into()]),
+                heartbeat: true,
+            }))
+        );
+
+        // Gossip about foreign network tells nothing
+        let foreign = Message {
+            gossip: Some(Gossip {
+                net: "10.0.0.0/8".parse().unwrap(),
+                peers: vec![[10, 0, 0, 3].into()],
+            }),
+            ..heartbeat
+        };
+        let Received::Alive(Some(status)) = r.handle(&socket, remote, &foreign.encode()) else {
+            panic!("heartbeat is not accepted");
+        };
+        assert_eq!(status.reaches, None);
+
+        // Full list may be truncated, so reach of the buddy stays unknown
+        let crowded = Message {
+            sender: 7,
+            gossip: Some(Gossip {
+                net: "127.0.0.0/8".parse().unwrap(),
+                peers: (0..MAX_GOSSIP_PEERS as u8)
+                    .map(|i| IpAddr::from([127, 0, 1, i]))
+                    .collect(),
+            }),
+            ..Message::new(Kind::Heartbeat)
+        };
+        let Received::Alive(Some(status)) = r.handle(&socket, remote, &crowded.encode()) else {
+            panic!("heartbeat is not accepted");
+        };
+        assert_eq!(status.reaches, None);
+    }
+}
@@ -0,0 +1,246 @@
+// SPDX-FileCopyrightText: 2026 Vladyslav Tsilytskyi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Ordered rules, which decide what every network adapter is used for
+
+use std::fmt;
+
+use pcap::Device;
+
+use crate::e;
+use crate::subnet::IpNet;
+
+const DEFAULT_RULES: &str = include_str!("../adapter_rules.txt");
+
+/// What the adapter is used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdapterClass {
+    Source,
+    Vpn,
+    Virtual,
+    Ignore,
+}
+
+impl fmt::Display for AdapterClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            AdapterClass::Source => "source",
+            AdapterClass::Vpn => "vpn",
+            AdapterClass::Virtual => "virtual",
+            AdapterClass::Ignore => "ignore",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Condition {
+    Name(String),
+    NamePrefix(String),
+    Desc(String),
+    PointToPoint,
+    Wireless,
+    Ipv4,
+    Ipv6,
+    Net(IpNet),
+}
+
+/// Single line of the rule file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub class: AdapterClass,
+    conditions: Vec<Condition>,
+    /// File and line, where the rule came from
+    pub origin: String,
+    /// Rule as written in the file
+    pub text: String,
+}
+
+impl Rule {
+    fn matches(&self, d: &Device) -> bool {
+        self.conditions.iter().all(|c| match c {
+            Condition::Name(n) => d.name == *n,
+            Condition::NamePrefix(p) => d.name.starts_with(p.as_str()),
+            Condition::Desc(s) => d.desc.clone().unwrap_or_default().contains(s.as_str()),
+            Condition::PointToPoint => d.addresses.iter().any(|a| a.dst_addr.is_some()),
+            Condition::Wireless => d.flags.is_wireless(),
+            Condition::Ipv4 => d.addresses.iter().any(|a| a.addr.is_ipv4()),
+            Condition::Ipv6 => d.addresses.iter().any(|a| a.addr.is_ipv6()),
+            Condition::Net(net) => d.addresses.iter().any(|a| net.contains(&a.addr)),
+        })
+    }
+}
+
+/// Split line by whitespace, double quotes keep spaces inside the value
+fn tokenize(line: &str) -> Result<Vec<String>, String> {
+    let mut tokens: Vec<String> = Vec::new();
+    let mut current: String = String::new();
+    let mut quoted: bool = false;
+    for c in line.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if quoted {
+        return Err("Unterminated quote.".to_string());
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    Ok(tokens)
+}
+
+fn parse_condition(token: &str) -> Result<Condition, String> {
+    if let Some(p) = token.strip_prefix("name^=") {
+        return Ok(Condition::NamePrefix(p.to_string()));
+    }
+    if let Some(n) = token.strip_prefix("name=") {
+        return Ok(Condition::Name(n.to_string()));
+    }
+    if let Some(s) = token.strip_prefix("desc~=") {
+        return Ok(Condition::Desc(s.to_string()));
+    }
+    if let Some(f) = token.strip_prefix("flag=") {
+        return match f {
+            "p2p" => Ok(Condition::PointToPoint),
+            "wireless" => Ok(Condition::Wireless),
+            _ => Err(format!("Unknown flag {}.", f)),
+        };
+    }
+    if let Some(f) = token.strip_prefix("family=") {
+        return match f {
+            "ipv4" => Ok(Condition::Ipv4),
+            "ipv6" => Ok(Condition::Ipv6),
+            _ => Err(format!("Unknown address family {}.", f)),
+        };
+    }
+    if let Some(n) = token.strip_prefix("net=") {
+        return Ok(Condition::Net(n.parse::<IpNet>()?));
+    }
+    Err(format!("Unknown condition {}.", token))
+}
+
+/// Parse rule file. Origin is used in error messages and explanations.
+pub fn parse_rules(text: &str, origin: &str) -> Result<Vec<Rule>, String> {
+    let mut rules: Vec<Rule> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let location = format!("{}:{}", origin, i + 1);
+        let tokens: Vec<String> = tokenize(line).map_err(|e| format!("{} {}", location, e))?;
+
+        let Some(first) = tokens.first() else {
+            continue;
+        };
+        let class: AdapterClass = match first.as_str() {
+            "source" => AdapterClass::Source,
+            "vpn" => AdapterClass::Vpn,
+            "virtual" => AdapterClass::Virtual,
+            "ignore" => AdapterClass::Ignore,
+            c => return Err(format!("{} Unknown adapter class {}.", location, c)),
+        };
+        if tokens.len() < 2 {
+            return Err(format!("{} Rule without conditions.", location));
+        }
+        let conditions: Vec<Condition> = tokens[1..]
+            .iter()
+            .map(|t| parse_condition(t))
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("{} {}", location, e))?;
+
+        rules.push(Rule {
+            class,
+            conditions,
+            origin: location,
+            text: line.to_string(),
+        });
+    }
+    Ok(rules)
+}
+
+/// Load user rules (if any) followed by built-in ones
+pub fn load_rules(path: &Option<String>) -> Result<Vec<Rule>, String> {
+    let mut rules: Vec<Rule> = match path {
+        Some(p) => {
+            let text: String = e!(std::fs::read_to_string(p));
+            parse_rules(&text, p)?
+        }
+        None => Vec::new(),
+    };
+    rules.extend(parse_rules(DEFAULT_RULES, "defaults")?);
+    Ok(rules)
+}
+
+/// Find the first rule that matches the device
+pub fn classify<'a>(rules: &'a [Rule], d: &Device) -> Option<&'a Rule> {
+    rules.iter().find(|r| r.matches(d))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_devices::device;
+    use std::net::IpAddr;
+
+    #[test]
+    fn default_rules_parse() {
+        let rules: Vec<Rule> = load_rules(&None).unwrap();
+        assert!(!rules.is_empty());
+        assert!(rules.iter().all(|r| r.origin.starts_with("defaults:")));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(parse_rules("vpn", "t").is_err());
+        assert!(parse_rules("tunnel name=wg0", "t").is_err());
+        assert!(parse_rules("vpn color=red", "t").is_err());
+        assert!(parse_rules("vpn flag=fast", "t").is_err());
+        assert!(parse_rules("vpn net=10.0.0.0", "t").is_err());
+        assert!(parse_rules("vpn net=10.0.0.0/33", "t").is_err());
+        assert!(parse_rules("vpn desc~=\"Radmin VPN", "t").is_err());
+
+        let e = parse_rules("# comment\n\nvpn name=wg0\nvpn mtu=1420", "my.rules").unwrap_err();
+        assert!(e.starts_with("my.rules:4 "));
+    }
+
+    #[test]
+    fn first_match_wins() {
+        let text = "\
+            ignore name=wg1\n\
+            vpn desc~=\"Radmin VPN\"\n\
+            vpn net=100.64.0.0/10 family=ipv4\n\
+            source name^=wg\n\
+            virtual net=fd00::/8\n";
+        let rules: Vec<Rule> = parse_rules(text, "t").unwrap();
+
+        let radmin = device(
+            "r",
+            Some("Famatech Radmin VPN Ethernet Adapter"),
+            [26, 1, 2, 3],
+        );
+        assert_eq!(classify(&rules, &radmin).unwrap().origin, "t:2");
+
+        let ts = device("ts", None, [100, 100, 1, 2]);
+        assert_eq!(classify(&rules, &ts).unwrap().class, AdapterClass::Vpn);
+
+        let wg1 = device("wg1", None, [10, 0, 0, 1]);
+        assert_eq!(classify(&rules, &wg1).unwrap().class, AdapterClass::Ignore);
+
+        let wg2 = device("wg2", None, [10, 0, 0, 1]);
+        assert_eq!(classify(&rules, &wg2).unwrap().class, AdapterClass::Source);
+
+        let ula = device("x", None, "fd12::1".parse::<IpAddr>().unwrap());
+        assert_eq!(classify(&rules, &ula).unwrap().class, AdapterClass::Virtual);
+
+        let other = device("lan", None, [192, 168, 0, 1]);
+        assert!(classify(&rules, &other).is_none());
+    }
+}
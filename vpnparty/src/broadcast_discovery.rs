@@ -0,0 +1,53 @@
+// SPDX-FileCopyrightText: 2026 Vladyslav Tsilytskyi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::time::Instant;
+
+use crate::debug;
+use crate::discovery::{Discovery, Mechanism};
+use crate::subnet::Ipv4Net;
+use crate::sup::{self, Settings};
+
+/// Peer discovery via directed broadcast of VPN network.
+/// Works on L2 overlays like ZeroTier or OpenVPN TAP, routed VPNs usually drop such packets.
+pub struct SubnetBroadcast {
+    vpnnet: Ipv4Net,
+    port: u16,
+}
+
+impl SubnetBroadcast {
+    pub fn new(vpnnet: Ipv4Net, port: u16) -> SubnetBroadcast {
+        SubnetBroadcast { vpnnet, port }
+    }
+}
+
+impl Discovery for SubnetBroadcast {
+    fn mechanism(&self) -> Mechanism {
+        Mechanism::Broadcast
+    }
+
+    fn start(&mut self) -> Result<UdpSocket, String> {
+        let socket_addr: SocketAddr = SocketAddr::new(IpAddr::V4(self.vpnnet.addr), self.port);
+        let socket: UdpSocket = UdpSocket::bind(socket_addr)
+            .map_err(|e| format!("Can't bind {}: {}", socket_addr, e))?;
+        socket.set_broadcast(true).map_err(|e| e.to_string())?;
+        debug!(
+            "Broadcasting Sup to {}:{} from {}",
+            self.vpnnet.broadcast(),
+            self.port,
+            self.vpnnet.addr
+        );
+        Ok(socket)
+    }
+
+    fn greet(
+        &mut self,
+        socket: &UdpSocket,
+        settings: &Settings,
+        _now: Instant,
+    ) -> Result<(), String> {
+        let dst: SocketAddr = SocketAddr::new(IpAddr::V4(self.vpnnet.broadcast()), self.port);
+        sup::send_hello(socket, dst, settings)
+    }
+}
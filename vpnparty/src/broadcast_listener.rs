@@ -3,45 +3,47 @@
 
 use std::sync::mpsc::Sender;
 
-use pcap::{Device, Packet};
+use pcap::Device;
 
-use crate::pcap_filter::{host_filter, port_filter};
+use crate::capture::capture_ip_packets;
+use crate::pcap_filter::{host6_filter, host_filter, port_filter};
+use crate::rebroadcast::FORWARD_MARK;
 use crate::{debug, e, error, Vpacket};
 
+const IPV6_ALL_NODES: &str = "ff02::1";
+
 pub fn listen_broadcast(srcdev: Device, btx: Sender<Vpacket>, ports: &[u16]) -> Result<(), String> {
     let port_filter: String = port_filter(ports);
-    let host_filter: String = host_filter(&srcdev.addresses);
-
-    let full_filter = format!("(ip broadcast) and {}{}", host_filter, port_filter);
+    // Packets of buddies, which were forwarded to LAN, are not sent back
+    let host_filter: String = format!(
+        "{} and not ((ip[4:2] & 0xfff0) = {})",
+        host_filter(&srcdev.addresses)?,
+        FORWARD_MARK
+    );
+
+    // IPv6 has no broadcast, games announce themselves to all-nodes multicast group instead
+    let full_filter = match host6_filter(&srcdev.addresses) {
+        Some(host6_filter) => format!(
+            "(((ip broadcast) and {}) or (ip6 and udp and (dst host {}) and {})){}",
+            host_filter, IPV6_ALL_NODES, host6_filter, port_filter
+        ),
+        None => format!("(ip broadcast) and {}{}", host_filter, port_filter),
+    };
     debug!("Broadcast filter: {}", full_filter);
 
-    // Setup Capture
-    let mut hw_cap = e!(e!(pcap::Capture::from_device(srcdev))
-        .immediate_mode(false)
-        .timeout(569) // This is a workaround, because immediate mode doesn't work in Win11 build
-        .open());
-
-    e!(hw_cap.filter(full_filter.as_str(), true));
-
-    // TODO: make a breaking condition
-    loop {
-        let p = hw_cap.next_packet();
-        let packet: Packet = match p {
-            Ok(p) => p,
-            Err(e) => {
-                let pcap::Error::TimeoutExpired = e else {
-                    error!("Error while receiving broadcast packet: {}", e);
-                    continue;
-                };
-                continue;
-            }
+    capture_ip_packets(srcdev, &full_filter, 569, "broadcast", |ip_packet| {
+        // IP and UDP headers without payload
+        let empty_len: usize = if ip_packet.first().map(|b| b >> 4) == Some(6) {
+            48
+        } else {
+            28
         };
-
-        if packet.header.len <= 42 {
+        if ip_packet.len() <= empty_len {
             error!("This packet is empty, skipping.");
-            continue;
+            return Ok(());
         }
 
-        e!(btx.send(Vpacket::B(packet.data.to_vec())));
-    }
+        e!(btx.send(Vpacket::B(ip_packet.to_vec())));
+        Ok(())
+    })
 }
@@ -0,0 +1,51 @@
+// SPDX-FileCopyrightText: 2026 Vladyslav Tsilytskyi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Capture loop shared by listeners of game and forwarded packets
+
+use pcap::{Device, Linktype, Packet};
+
+use crate::link_layer::strip_link_header;
+use crate::{debug, e, error};
+
+/// Capture IP packets, which pass the filter, until handler fails.
+/// Kind of packets is used in log messages. Timeout is a workaround,
+/// because immediate mode doesn't work in Win11 build.
+pub fn capture_ip_packets(
+    device: Device,
+    filter: &str,
+    timeout_ms: i32,
+    kind: &str,
+    mut handle: impl FnMut(&[u8]) -> Result<(), String>,
+) -> Result<(), String> {
+    let mut cap = e!(e!(pcap::Capture::from_device(device))
+        .immediate_mode(false)
+        .timeout(timeout_ms)
+        .open());
+
+    let linktype: Linktype = cap.get_datalink();
+    debug!("{} capture link type: {:?}", kind, linktype);
+
+    e!(cap.filter(filter, true));
+
+    loop {
+        let packet: Packet = match cap.next_packet() {
+            Ok(p) => p,
+            Err(pcap::Error::TimeoutExpired) => continue,
+            Err(e) => {
+                error!("Error while receiving {} packet: {}", kind, e);
+                continue;
+            }
+        };
+
+        let ip_packet: &[u8] = match strip_link_header(linktype, packet.data) {
+            Ok(p) => p,
+            Err(e) => {
+                debug!("Skipping {} frame: {}", kind, e);
+                continue;
+            }
+        };
+
+        handle(ip_packet)?;
+    }
+}
@@ -1,12 +1,17 @@
 // SPDX-FileCopyrightText: 2026 Vladyslav Tsilytskyi
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use std::{net::Ipv4Addr, str::FromStr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
 
 use pcap::Device;
 
-use crate::network_devices::{get_promising_devices, print_devices};
-use crate::{e, logger};
+use crate::adapter_rules::{load_rules, Rule};
+use crate::auth::Secret;
+use crate::network_devices::{get_promising_devices, print_devices, AddressPreference};
+use crate::subnet::IpNet;
+use crate::{e, logger, rebroadcast, relay, sup};
 
 const HELP: &str = "\
 vpnparty is a next gen LAN party.
@@ -16,34 +21,110 @@ USAGE:
 
 FLAGS:
   -h, --help              Prints help information
-  --devices               List available network adapters
+  --devices               List available network adapters and explain how they are classified
   --monochrome            Don't use colors in output
   --no-multicast          Disable multicast discovery
   --no-udping             Disable ping discovery
+  --broadcast             Enable discovery via directed broadcast of VPN network, like 10.0.0.255.
+                          Works on L2 VPNs, like ZeroTier or OpenVPN in TAP mode.
+  --lan                   Send game broadcasts and mDNS of buddies to LAN, so computers without vpnparty see them too.
+                          IPv6 VPN addresses need --tunnel.
+  --bridge                Relay game broadcasts of buddies between VPN connections, so players of every VPN see each other.
+                          Relayed broadcasts come from VPN address of this computer, unicast isn't relayed.
+                          IPv6 VPN addresses need --tunnel.
+  -V, --version           Prints application and Sup protocol versions
 
 OPTIONS:
   -v, --verbose  NUMBER        Verbosity level [0-2] where 1 is debug and 2 trace level.
-  -s, --srcdev   \"NAME\"        Name of the device, which receives broadcast packets.
+  -s, --srcdev   \"NAME\"        Device, which receives broadcast packets.
                                Usually this is your Ethernet or Wi-Fi adapter, but might be a Hyper-V Virtual adapter.
+                               Device is selected by name, IP address or part of description (see --devices).
                                Example: --srcdev=\"\\Device\\NPF_{D0B8AF5E-B11D-XXXX-XXXX-XXXXXXXXXXXX}\"
+                               Example: --srcdev eth0
+                               Example: --srcdev 192.168.0.11
   -d, --dstdev \"NAME\" \"NAME\"   Space-separated list of your VPN connection devices.
                                Supported VPNs are Wireguard and OpenVPN, altough other should work as well.
+                               Devices are selected the same way as --srcdev.
                                Example --dstdev \"\\Device\\NPF_{CFB8AF5E-A00C-XXXX-XXXX-XXXXXXXXXXXX}\" \"\\Device\\NPF_{E1C9B06F-C22E-XXXX-XXXX-XXXXXXXXXXXX}\"
+                               Example --dstdev wg0 tun0
+                               Example --dstdev WireGuard
   -b, --buddyip IP IP          Space-separated list of your teammates IP addresses.
                                Usually statically assigned in Wireguard/OpenVPN configuration.
                                Example: --buddyip 10.2.0.5 10.2.0.6 10.2.0.9 10.2.0.15
+                               Example: --buddyip fd00::5 fd00::6
+                               Networks are not buddies, but scan targets of UDP discovery, see --scan.
+                               Example: --buddyip 10.2.0.5 10.2.1.0/24
   -p, --port PORT PORT         Capture broadcast packets only for given ports. Predefined constants are \"all\" (default) and \"known\".
                                Example: -p 4549 6112 42801
                                Example: -p known
   --mip IP                     Specify custom multicast IP (default is 239.1.2.3). Must be same for all buddies.
                                Must belong to the multicast range! Best option is 239.*.*.* range.
                                Example: --mip 239.240.241.242
+  --mip6 IP                    Specify custom IPv6 multicast IP (default is ff15::1:2:3). Must be same for all buddies.
+                               Example: --mip6 ff15::7
   --mport PORT                 Specify custom multicast port (default is 54929). Must be same for all buddies.
                                Example: --mport 61111
+  --bport PORT                 Specify custom broadcast discovery port (default is 54931). Must be same for all buddies.
+                               Example: --bport 61113
   --uport PORT                 Specify custom udp discovery port (default is 54928). Must be same for all buddies.
                                Example: --uport 61112
+  --scan CIDR CIDR             Networks to sweep by UDP discovery instead of own VPN network (or its /20 part).
+                               Network must belong to the VPN adapter and be at most /16 (IPv4) or /112 (IPv6).
+                               Example: --scan 10.147.17.0/24 10.147.30.0/24
+                               Example: --scan fd00::/112
+  --pps NUMBER                 Limit UDP discovery to NUMBER packets per second (default is 500, 0 disables).
+                               Keeps sweep of a large network below rate limit of the VPN.
+                               Example: --pps 100
+  --netmask CIDR CIDR          VPN networks for adapters, which report /32 netmask (/24 or /64 is assumed otherwise).
+                               Network is applied to the VPN adapter, which address belongs to it.
+                               Example: --netmask 100.64.0.0/10 10.147.0.0/16
+                               Example: --netmask fd7a:115c:a1e0::/48
+  --vpnaddr MODE               Which IPv4 and IPv6 addresses of VPN adapter to use: \"first\" (default), \"all\" or list of networks.
+                               Every used address is a separate connection to buddies.
+                               Example: --vpnaddr all
+                               Example: --vpnaddr 10.8.0.0/16 10.9.0.0/16
+                               Example: --vpnaddr 10.8.0.0/16 fd00::/8
+  --rebroadcast[=IP]           Turn packets forwarded by buddies into broadcasts on this host (to 255.255.255.255 by default).
+                               For games, which ignore announcements not sent to a broadcast address. Requires Npcap loopback support.
+                               Example: --rebroadcast=192.168.0.255
+  --tunnel CIDR CIDR           VPN networks, which send game packets to buddies via UDP tunnel (port 54932) instead of injecting them.
+                               Used with buddies, which enable tunnel too. Helps with VPNs, which drop injected packets.
+                               Example: --tunnel 10.147.17.0/24
+  --prefer CIDR CIDR           VPN networks in order of preference for buddies reachable via several VPNs.
+                               Otherwise the path with the lowest round trip time is used.
+                               Example: --prefer 10.147.17.0/24 100.64.0.0/10
+  --relay[=HOPS]               Relay game broadcasts of buddies to buddies, who can't reach them directly (2 hops by default).
+                               For VPNs, where not every buddy sees every other one, like hub-and-spoke setups.
+                               IPv6 VPN addresses need --tunnel.
+                               Example: --relay=1
+  --refresh SECONDS            Repeat peer discovery to find late joiners (default is 15, 0 disables).
+                               Interval backs off up to 16x while nobody new shows up.
+                               Example: --refresh 60
+  --secret TEXT                Party secret. Discovery messages are signed with it and unsigned ones are rejected.
+                               Must be same for all buddies.
+                               Example: --secret \"correct horse battery staple\"
+  --party NAME                 Party name. Buddies in other parties on the same VPN are reported, but ignored.
+                               Must be same for all buddies of the party.
+                               Example: --party \"Blue team\"
+  --lighthouse IP:PORT         Register at lighthouse, which tells about other buddies of the party.
+                               Useful for large VPNs, where multicast and UDP discovery don't work.
+                               Port may be omitted (default is 54930).
+                               Example: --lighthouse 100.64.0.1:54930
+  --rules FILE                 Network adapter classification rules, checked before built-in ones.
+                               See adapter_rules.txt in the source code for the format and defaults.
+                               Example: --rules my_adapters.txt
 ";
 
+/// Base interval of repeated peer discovery
+const DEFAULT_REFRESH_SECS: u64 = 15;
+
+/// Pace of UDP discovery, packets per second
+const DEFAULT_PPS: u32 = 500;
+
+/// Largest networks to sweep have 65536 addresses
+const MIN_SCAN_PREFIX: u8 = 16;
+const MIN_SCAN6_PREFIX: u8 = 112;
+
 const KNOWN_PORTS: [u16; 3] = [
     4549,  // Torchlight 2
     6112,  // Warcraft 3
@@ -55,13 +136,38 @@ const KNOWN_PORTS: [u16; 3] = [
 pub struct Arguments {
     pub srcdev: Option<String>,
     pub dstdev: Vec<String>,
-    pub buddyip: Vec<Ipv4Addr>,
+    pub buddyip: Vec<IpAddr>,
+    /// Networks to sweep by UDP discovery
+    pub scan: Vec<IpNet>,
+    pub pps: u32,
     pub port: Vec<u16>,
     pub mip: Option<Ipv4Addr>,
+    pub mip6: Option<Ipv6Addr>,
     pub mport: Option<u16>,
     pub uport: Option<u16>,
+    pub bport: Option<u16>,
     pub no_multicast: bool,
     pub no_udping: bool,
+    pub broadcast: bool,
+    pub refresh: Duration,
+    pub secret: Option<Secret>,
+    pub party: Option<String>,
+    pub lighthouse: Option<SocketAddr>,
+    pub rules: Option<String>,
+    pub netmask: Vec<IpNet>,
+    pub vpnaddr: AddressPreference,
+    /// Preferred VPN networks, the first one is the best
+    pub prefer: Vec<IpNet>,
+    /// Broadcast address of forwarded packets, re-broadcast is disabled if None
+    pub rebroadcast: Option<Ipv4Addr>,
+    /// VPN networks, which use UDP tunnel
+    pub tunnel: Vec<IpNet>,
+    /// Broadcasts of buddies go to LAN
+    pub lan: bool,
+    /// Broadcasts of buddies are relayed between directions
+    pub bridge: bool,
+    /// Hop limit of broadcasts relayed to buddies of the same direction, relay is disabled if None
+    pub relay: Option<u8>,
 }
 
 /// Parse command line arguments
@@ -69,17 +175,35 @@ pub fn parse_args() -> Result<Arguments, String> {
     use lexopt::prelude::*;
 
     let max_verbosity = 3u8;
-    let dev_name_len = 50;
 
     let mut srcdev: Option<String> = None;
     let mut dstdev: Vec<String> = Vec::new();
-    let mut buddyip: Vec<Ipv4Addr> = Vec::new();
+    let mut buddyip: Vec<IpAddr> = Vec::new();
+    let mut scan: Vec<IpNet> = Vec::new();
+    let mut pps: u32 = DEFAULT_PPS;
     let mut port: Vec<u16> = Vec::new();
     let mut mip: Option<Ipv4Addr> = None;
+    let mut mip6: Option<Ipv6Addr> = None;
     let mut mport: Option<u16> = None;
     let mut uport: Option<u16> = None;
+    let mut bport: Option<u16> = None;
     let mut no_multicast: bool = false;
     let mut no_udping: bool = false;
+    let mut broadcast: bool = false;
+    let mut refresh: Duration = Duration::from_secs(DEFAULT_REFRESH_SECS);
+    let mut secret: Option<Secret> = None;
+    let mut party: Option<String> = None;
+    let mut lighthouse: Option<SocketAddr> = None;
+    let mut rules: Option<String> = None;
+    let mut netmask: Vec<IpNet> = Vec::new();
+    let mut vpnaddr: AddressPreference = AddressPreference::First;
+    let mut prefer: Vec<IpNet> = Vec::new();
+    let mut rebroadcast: Option<Ipv4Addr> = None;
+    let mut tunnel: Vec<IpNet> = Vec::new();
+    let mut lan: bool = false;
+    let mut bridge: bool = false;
+    let mut relay: Option<u8> = None;
+    let mut devices: bool = false;
 
     let mut parser = lexopt::Parser::from_env();
     while let Some(arg) = e!(parser.next()) {
@@ -90,20 +214,16 @@ pub fn parse_args() -> Result<Arguments, String> {
             }
             Short('s') | Long("srcdev") => {
                 let s = e!(e!(parser.value()).string());
-                if !s.starts_with("\\Device\\NPF_{") || !s.ends_with("}") || s.len() != dev_name_len
-                {
-                    return Err(format!("Invalid device name {}.", s));
+                if s.is_empty() {
+                    return Err("Empty device name.".to_string());
                 }
                 srcdev = Some(s);
             }
             Short('d') | Long("dstdev") => {
                 for d in e!(parser.values()) {
                     let s = e!(d.string());
-                    if !s.starts_with("\\Device\\NPF_{")
-                        || !s.ends_with("}")
-                        || s.len() != dev_name_len
-                    {
-                        return Err(format!("Invalid device name {}.", s));
+                    if s.is_empty() {
+                        return Err("Empty device name.".to_string());
                     }
                     dstdev.push(s);
                 }
@@ -111,8 +231,12 @@ pub fn parse_args() -> Result<Arguments, String> {
             Short('b') | Long("buddyip") => {
                 for ipstr in e!(parser.values()) {
                     let s = e!(ipstr.string());
-                    let a = e!(Ipv4Addr::from_str(&s));
-                    buddyip.push(a);
+                    if s.contains('/') {
+                        scan.push(parse_scan_target(&s)?);
+                    } else {
+                        let a = e!(IpAddr::from_str(&s));
+                        buddyip.push(a);
+                    }
                 }
             }
             Short('p') | Long("port") => {
@@ -139,6 +263,14 @@ pub fn parse_args() -> Result<Arguments, String> {
                 let a = e!(Ipv4Addr::from_str(&s));
                 mip = Some(a);
             }
+            Long("mip6") => {
+                let s = e!(e!(parser.value()).string());
+                let a = e!(Ipv6Addr::from_str(&s));
+                if !a.is_multicast() {
+                    return Err(format!("{} is not a multicast address.", a));
+                }
+                mip6 = Some(a);
+            }
             Long("mport") => {
                 let port: u16 = e!(e!(parser.value()).parse::<u16>());
                 mport = Some(port);
@@ -147,14 +279,100 @@ pub fn parse_args() -> Result<Arguments, String> {
                 let port: u16 = e!(e!(parser.value()).parse::<u16>());
                 uport = Some(port);
             }
+            Long("bport") => {
+                let port: u16 = e!(e!(parser.value()).parse::<u16>());
+                bport = Some(port);
+            }
+            Short('V') | Long("version") => {
+                println!(
+                    "vpnparty {}, Sup protocol v{}",
+                    env!("CARGO_PKG_VERSION"),
+                    sup::PROTOCOL_VERSION
+                );
+                std::process::exit(0);
+            }
             Short('h') | Long("help") => {
                 println!("{}", HELP);
                 std::process::exit(0);
             }
             Long("devices") => {
-                let devs: Vec<Device> = get_promising_devices()?;
-                print_devices(&devs);
-                std::process::exit(0);
+                // Postpone till --rules is parsed
+                devices = true;
+            }
+            Long("netmask") => {
+                for cidr in e!(parser.values()) {
+                    let s = e!(cidr.string());
+                    netmask.push(IpNet::from_str(&s)?);
+                }
+            }
+            Long("vpnaddr") => {
+                let mut values: Vec<String> = Vec::new();
+                for v in e!(parser.values()) {
+                    values.push(e!(v.string()));
+                }
+                vpnaddr = parse_vpnaddr(&values)?;
+            }
+            Long("refresh") => {
+                let secs: u64 = e!(e!(parser.value()).parse::<u64>());
+                refresh = Duration::from_secs(secs);
+            }
+            Long("scan") => {
+                for netstr in e!(parser.values()) {
+                    scan.push(parse_scan_target(&e!(netstr.string()))?);
+                }
+            }
+            Long("rebroadcast") => {
+                rebroadcast = Some(match parser.optional_value() {
+                    Some(ip) => e!(Ipv4Addr::from_str(&e!(ip.string()))),
+                    None => Ipv4Addr::BROADCAST,
+                });
+            }
+            Long("relay") => {
+                let hops: u8 = match parser.optional_value() {
+                    Some(hops) => e!(e!(hops.string()).parse::<u8>()),
+                    None => relay::DEFAULT_RELAY_HOPS,
+                };
+                if hops == 0 {
+                    return Err("--relay needs at least 1 hop.".to_string());
+                }
+                relay = Some(std::cmp::min(hops, rebroadcast::MAX_HOPS));
+            }
+            Long("tunnel") => {
+                for netstr in e!(parser.values()) {
+                    tunnel.push(IpNet::from_str(&e!(netstr.string()))?);
+                }
+            }
+            Long("prefer") => {
+                for netstr in e!(parser.values()) {
+                    prefer.push(IpNet::from_str(&e!(netstr.string()))?);
+                }
+            }
+            Long("pps") => {
+                pps = e!(e!(parser.value()).parse::<u32>());
+            }
+            Long("secret") => {
+                secret = Some(Secret::new(&e!(e!(parser.value()).string()))?);
+            }
+            Long("party") => {
+                let name: String = e!(e!(parser.value()).string());
+                if name.is_empty() || name.len() > sup::MAX_PARTY_LEN {
+                    return Err(format!(
+                        "Party name must be 1 to {} bytes long.",
+                        sup::MAX_PARTY_LEN
+                    ));
+                }
+                party = Some(name);
+            }
+            Long("lighthouse") => {
+                let s: String = e!(e!(parser.value()).string());
+                let addr: SocketAddr = match IpAddr::from_str(&s) {
+                    Ok(ip) => SocketAddr::new(ip, crate::LIGHTHOUSE_PORT),
+                    Err(_) => e!(SocketAddr::from_str(&s)),
+                };
+                lighthouse = Some(addr);
+            }
+            Long("rules") => {
+                rules = Some(e!(e!(parser.value()).string()));
             }
             Long("monochrome") => {
                 crate::logger::set_monochrome();
@@ -165,19 +383,108 @@ pub fn parse_args() -> Result<Arguments, String> {
             Long("no-udping") => {
                 no_udping = true;
             }
+            Long("broadcast") => {
+                broadcast = true;
+            }
+            Long("lan") => {
+                lan = true;
+            }
+            Long("bridge") => {
+                bridge = true;
+            }
             _ => return Err(format!("Unexpected command line option {:?}.", arg)),
         }
     }
 
+    if devices {
+        let devs: Vec<Device> = get_promising_devices()?;
+        let r: Vec<Rule> = load_rules(&rules)?;
+        print_devices(&devs, &r);
+        std::process::exit(0);
+    }
+
     Ok(Arguments {
         srcdev,
         dstdev,
         buddyip,
+        scan,
+        pps,
         port,
         mip,
+        mip6,
         mport,
         uport,
+        bport,
         no_multicast,
         no_udping,
+        broadcast,
+        refresh,
+        secret,
+        party,
+        lighthouse,
+        rules,
+        netmask,
+        vpnaddr,
+        prefer,
+        rebroadcast,
+        tunnel,
+        lan,
+        bridge,
+        relay,
     })
 }
+
+/// Either a single "first" or "all", or list of networks and addresses
+fn parse_vpnaddr(values: &[String]) -> Result<AddressPreference, String> {
+    match values {
+        [v] if v == "first" => return Ok(AddressPreference::First),
+        [v] if v == "all" => return Ok(AddressPreference::All),
+        _ => {}
+    }
+    let mut nets: Vec<IpNet> = Vec::new();
+    for s in values {
+        if s == "first" || s == "all" {
+            return Err(format!(
+                "--vpnaddr {} can't be combined with other values.",
+                s
+            ));
+        }
+        // Single address is a network of one host
+        let n: IpNet = match IpAddr::from_str(s) {
+            Ok(ip) => IpNet::host(ip),
+            Err(_) => IpNet::from_str(s)?,
+        };
+        nets.push(n);
+    }
+    Ok(AddressPreference::Networks(nets))
+}
+
+/// Network to sweep, small enough to finish in reasonable time
+fn parse_scan_target(s: &str) -> Result<IpNet, String> {
+    let net: IpNet = IpNet::from_str(s)?;
+    let min_prefix: u8 = match net {
+        IpNet::V4(_) => MIN_SCAN_PREFIX,
+        IpNet::V6(_) => MIN_SCAN6_PREFIX,
+    };
+    if net.prefix() < min_prefix {
+        return Err(format!(
+            "Network {} is too large to scan, split it into /{} networks or smaller.",
+            net, min_prefix
+        ));
+    }
+    Ok(net)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scan_target() {
+        assert_eq!(parse_scan_target("10.147.0.0/16").unwrap().prefix(), 16);
+        assert_eq!(parse_scan_target("fd00::/112").unwrap().prefix(), 112);
+        assert!(parse_scan_target("10.146.0.0/15").is_err());
+        assert!(parse_scan_target("fd00::/111").is_err());
+        assert!(parse_scan_target("10.147.0.0").is_err());
+    }
+}
@@ -0,0 +1,353 @@
+// SPDX-FileCopyrightText: 2026 Vladyslav Tsilytskyi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Peer discovery mechanisms and the loop they share
+
+use std::collections::HashSet;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::liveness::HEARTBEAT_INTERVAL;
+use crate::refresh::{is_timeout, Refresh};
+use crate::subnet::IpNet;
+use crate::sup::{self, Dropped, Received, Responder, Settings, Status};
+use crate::{debug, e, error, trace, Vpacket};
+
+/// Stop request is noticed within this time
+const STOP_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How the buddy was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mechanism {
+    Multicast,
+    Udping,
+    Broadcast,
+    Lighthouse,
+}
+
+impl Mechanism {
+    /// Letter of packet trace
+    pub fn letter(&self) -> char {
+        match self {
+            Mechanism::Multicast => 'M',
+            Mechanism::Udping => 'U',
+            Mechanism::Broadcast => 'S',
+            Mechanism::Lighthouse => 'H',
+        }
+    }
+}
+
+impl fmt::Display for Mechanism {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mechanism::Multicast => write!(f, "Multicast"),
+            Mechanism::Udping => write!(f, "UDP"),
+            Mechanism::Broadcast => write!(f, "Broadcast"),
+            Mechanism::Lighthouse => write!(f, "Lighthouse"),
+        }
+    }
+}
+
+/// What discovery learned about the buddy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Peer {
+    /// Buddy answered Sup
+    Alive(IpAddr),
+    /// Buddy said goodbye
+    Bye(IpAddr),
+}
+
+/// Peer event of a direction, tagged with the mechanism which produced it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub direction_id: usize,
+    pub mechanism: Mechanism,
+    pub peer: Peer,
+    /// Sup sender ID, unknown for legacy buddies
+    pub id: Option<u64>,
+    /// Port, where buddy accepts forwarded packets via UDP tunnel
+    pub tunnel: Option<u16>,
+    /// Buddies of the direction, which the buddy reaches directly, if it gossips them
+    pub reaches: Option<Vec<IpAddr>>,
+    /// Event comes from heartbeat, which always tells the tunnel port
+    pub heartbeat: bool,
+}
+
+/// Discovery mechanism of a single direction. Received Sup messages are handled by the shared loop.
+pub trait Discovery: Send {
+    fn mechanism(&self) -> Mechanism;
+
+    /// Open socket, which greets buddies and receives their Sup
+    fn start(&mut self) -> Result<UdpSocket, String>;
+
+    /// Greet buddies. Repeated to find late joiners.
+    fn greet(
+        &mut self,
+        socket: &UdpSocket,
+        settings: &Settings,
+        now: Instant,
+    ) -> Result<(), String>;
+
+    /// Work spread in time, like paced sweep. Returns when to call it again.
+    fn tick(
+        &mut self,
+        _socket: &UdpSocket,
+        _settings: &Settings,
+        _now: Instant,
+    ) -> Option<Instant> {
+        None
+    }
+
+    /// Port to greet gossiped buddies at, if it isn't the port of the socket
+    fn probe_port(&self) -> Option<u16> {
+        None
+    }
+
+    /// Clean up before the socket is closed
+    fn stop(&mut self, _socket: &UdpSocket) {}
+}
+
+/// Run discovery in its own thread until stop is requested
+pub fn spawn(
+    direction_id: usize,
+    vpnnet: IpNet,
+    tx: Sender<Vpacket>,
+    discovery: Box<dyn Discovery>,
+    settings: Settings,
+    stop: Arc<AtomicBool>,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut discovery = discovery;
+        let mechanism: Mechanism = discovery.mechanism();
+        if let Err(e) = run(
+            direction_id,
+            vpnnet,
+            &tx,
+            discovery.as_mut(),
+            &settings,
+            &stop,
+        ) {
+            error!("{} discovery stopped: {}", mechanism, e);
+        }
+    })
+}
+
+fn run(
+    direction_id: usize,
+    vpnnet: IpNet,
+    tx: &Sender<Vpacket>,
+    discovery: &mut dyn Discovery,
+    settings: &Settings,
+    stop: &AtomicBool,
+) -> Result<(), String> {
+    let mechanism: Mechanism = discovery.mechanism();
+    let socket: UdpSocket = discovery.start()?;
+    let own_ip: IpAddr = e!(socket.local_addr()).ip();
+    let send = |peer: Peer, status: Option<Status>| -> Result<(), String> {
+        let (id, tunnel, reaches, heartbeat) = match status {
+            Some(s) => (Some(s.id), s.tunnel, s.reaches, s.heartbeat),
+            None => (None, None, None, false),
+        };
+        e!(tx.send(Vpacket::P(Event {
+            direction_id,
+            mechanism,
+            peer,
+            id,
+            tunnel,
+            reaches,
+            heartbeat,
+        })));
+        Ok(())
+    };
+
+    let mut buf = [0; sup::MAX_MESSAGE_LEN];
+    let mut responder: Responder = Responder::new(settings.clone()).with_network(vpnnet);
+    if let Some(port) = discovery.probe_port() {
+        responder = responder.with_probe_port(port);
+    }
+    let mut refresh: Refresh = Refresh::new(settings.refresh);
+    let mut known: HashSet<IpAddr> = HashSet::new();
+    let mut next_poke: Instant = Instant::now() + HEARTBEAT_INTERVAL;
+    let mut dropped: Dropped = Dropped::default();
+
+    // Discovery keeps answering buddies, even if it can't greet them now
+    if let Err(e) = discovery.greet(&socket, settings, Instant::now()) {
+        error!("{} discovery on {} failed: {}", mechanism, own_ip, e);
+    }
+
+    while !stop.load(Ordering::Relaxed) {
+        let now: Instant = Instant::now();
+        if refresh.is_due(now) {
+            // Late joiners and reconnected buddies answer this one
+            trace!("Repeating {} discovery on {}", mechanism, own_ip);
+            if let Err(e) = discovery.greet(&socket, settings, now) {
+                error!("{} discovery on {} failed: {}", mechanism, own_ip, e);
+            }
+            refresh.done(now);
+        }
+        let next_tick: Option<Instant> = discovery.tick(&socket, settings, now);
+        if now >= next_poke {
+            responder.poke_legacy(&socket);
+            next_poke = now + HEARTBEAT_INTERVAL;
+            if responder.dropped() != dropped {
+                dropped = responder.dropped();
+                debug!("{} discovery on {} dropped {}.", mechanism, own_ip, dropped);
+            }
+        }
+        let wait: Duration = std::cmp::min(
+            next_poke.saturating_duration_since(now),
+            STOP_CHECK_INTERVAL,
+        );
+        let wait: Duration = refresh.wait(now).map_or(wait, |r| std::cmp::min(r, wait));
+        let wait: Duration = next_tick.map_or(wait, |t| {
+            std::cmp::min(t.saturating_duration_since(now), wait)
+        });
+        // Zero read timeout is an error for sockets
+        e!(socket.set_read_timeout(Some(std::cmp::max(wait, Duration::from_millis(1)))));
+
+        let (len, remote_addr) = match socket.recv_from(&mut buf) {
+            Ok(p) => p,
+            Err(e) => {
+                // Windows reports ICMP port unreachable of previous send here
+                if !is_timeout(&e) && e.kind() != io::ErrorKind::ConnectionReset {
+                    error!("Error while receiving {} packet: {}", mechanism, e);
+                }
+                continue;
+            }
+        };
+
+        let buddy_ip: IpAddr = remote_addr.ip();
+        if buddy_ip == own_ip {
+            debug!("Sup from {}!", own_ip);
+            continue;
+        }
+
+        match responder.handle(&socket, remote_addr, &buf[..len]) {
+            Received::Alive(status) => {
+                if known.insert(buddy_ip) {
+                    refresh.reset(Instant::now());
+                }
+                send(Peer::Alive(buddy_ip), status)?;
+            }
+            Received::Bye(id) => {
+                known.remove(&buddy_ip);
+                send(
+                    Peer::Bye(buddy_ip),
+                    id.map(|id| Status {
+                        id,
+                        tunnel: None,
+                        reaches: None,
+                        heartbeat: false,
+                    }),
+                )?;
+            }
+            Received::Ignored => {}
+        }
+    }
+
+    discovery.stop(&socket);
+    debug!("{} discovery on {} stopped.", mechanism, own_ip);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sup::{Kind, Message};
+    use std::net::SocketAddr;
+    use std::sync::mpsc::{self, Receiver};
+    use std::sync::Mutex;
+
+    /// Discovery, which records its calls and can't greet anybody
+    struct Fake {
+        calls: Arc<Mutex<Vec<&'static str>>>,
+        bound: Sender<SocketAddr>,
+    }
+
+    impl Discovery for Fake {
+        fn mechanism(&self) -> Mechanism {
+            Mechanism::Udping
+        }
+
+        fn start(&mut self) -> Result<UdpSocket, String> {
+            self.calls.lock().unwrap().push("start");
+            let socket = e!(UdpSocket::bind("127.0.0.1:0"));
+            e!(self.bound.send(e!(socket.local_addr())));
+            Ok(socket)
+        }
+
+        fn greet(&mut self, _: &UdpSocket, _: &Settings, _: Instant) -> Result<(), String> {
+            self.calls.lock().unwrap().push("greet");
+            Err("network is unreachable".to_string())
+        }
+
+        fn stop(&mut self, _: &UdpSocket) {
+            self.calls.lock().unwrap().push("stop");
+        }
+    }
+
+    #[test]
+    fn greet_receive_stop() {
+        let calls: Arc<Mutex<Vec<&'static str>>> = Arc::new(Mutex::new(Vec::new()));
+        let (bound_tx, bound_rx) = mpsc::channel();
+        let (tx, rx): (Sender<Vpacket>, Receiver<Vpacket>) = mpsc::channel();
+        let stop: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+        let fake = Fake {
+            calls: calls.clone(),
+            bound: bound_tx,
+        };
+        let handle = spawn(
+            3,
+            "127.0.0.1/8".parse().unwrap(),
+            tx,
+            Box::new(fake),
+            Settings::default(),
+            stop.clone(),
+        );
+
+        // Failed greeting doesn't stop discovery, buddy's hello is still answered
+        let addr: SocketAddr = bound_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        let buddy = UdpSocket::bind("127.0.0.2:0").unwrap();
+        buddy
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let hello: Vec<u8> = Message {
+            sender: 7,
+            tunnel: Some(54932),
+            ..Message::new(Kind::Hello)
+        }
+        .encode();
+        buddy.send_to(&hello, addr).unwrap();
+
+        let mut buf = [0; sup::MAX_MESSAGE_LEN];
+        let (len, from) = buddy.recv_from(&mut buf).unwrap();
+        assert_eq!(from, addr);
+        assert_eq!(Message::decode(&buf[..len]).unwrap().kind, Kind::Reply);
+
+        match rx.recv_timeout(Duration::from_secs(5)).unwrap() {
+            Vpacket::P(event) => assert_eq!(
+                event,
+                Event {
+                    direction_id: 3,
+                    mechanism: Mechanism::Udping,
+                    peer: Peer::Alive(buddy.local_addr().unwrap().ip()),
+                    id: Some(7),
+                    tunnel: Some(54932),
+                    reaches: None,
+                    heartbeat: false,
+                }
+            ),
+            _ => panic!("Discovery sent something else than peer event"),
+        }
+
+        stop.store(true, Ordering::Relaxed);
+        handle.join().unwrap();
+        assert_eq!(*calls.lock().unwrap(), ["start", "greet", "stop"]);
+    }
+}
@@ -0,0 +1,107 @@
+// SPDX-FileCopyrightText: 2026 Vladyslav Tsilytskyi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::net::{SocketAddr, UdpSocket};
+use std::time::Instant;
+
+use crate::discovery::{Discovery, Mechanism};
+use crate::liveness::HEARTBEAT_INTERVAL;
+use crate::subnet::IpNet;
+use crate::sup::{Gossip, Kind, Message, Settings, MAX_GOSSIP_PEERS};
+use crate::{debug, error, trace};
+
+/// Peer discovery via lighthouse. Registration is repeated as often as heartbeats,
+/// so lighthouse forgets us soon after we leave.
+pub struct Lighthouse {
+    vpnnet: IpNet,
+    lighthouse: SocketAddr,
+    probe_port: u16,
+    next_register: Instant,
+}
+
+impl Lighthouse {
+    /// Buddies from lighthouse are greeted at probe port
+    pub fn new(vpnnet: IpNet, lighthouse: SocketAddr, probe_port: u16) -> Lighthouse {
+        Lighthouse {
+            vpnnet,
+            lighthouse,
+            probe_port,
+            next_register: Instant::now(),
+        }
+    }
+
+    fn register(
+        &mut self,
+        socket: &UdpSocket,
+        settings: &Settings,
+        now: Instant,
+    ) -> Result<(), String> {
+        trace!(
+            "Registering {} at lighthouse {}",
+            self.vpnnet.addr(),
+            self.lighthouse
+        );
+        self.next_register = now + HEARTBEAT_INTERVAL;
+        let register: Message = Message {
+            gossip: Some(Gossip {
+                net: self.vpnnet,
+                peers: Vec::new(),
+            }),
+            // Lighthouse never answers with more bytes than it got
+            padding: roster_len(self.vpnnet),
+            ..Message::new(Kind::Register)
+        };
+        let msg: Vec<u8> = register.encode_for(self.vpnnet.addr(), settings);
+        socket
+            .send_to(&msg, self.lighthouse)
+            .map_err(|e| format!("Can't reach lighthouse {}: {}", self.lighthouse, e))?;
+        Ok(())
+    }
+}
+
+/// Room for the full roster in registration
+fn roster_len(vpnnet: IpNet) -> usize {
+    let addr_len: usize = match vpnnet {
+        IpNet::V4(_) => 4,
+        IpNet::V6(_) => 16,
+    };
+    addr_len * MAX_GOSSIP_PEERS
+}
+
+impl Discovery for Lighthouse {
+    fn mechanism(&self) -> Mechanism {
+        Mechanism::Lighthouse
+    }
+
+    fn start(&mut self) -> Result<UdpSocket, String> {
+        debug!(
+            "Registering {} at lighthouse {}",
+            self.vpnnet.addr(),
+            self.lighthouse
+        );
+        // Roster is answered with greetings to buddies, their replies come here too
+        UdpSocket::bind(SocketAddr::new(self.vpnnet.addr(), 0)).map_err(|e| e.to_string())
+    }
+
+    fn greet(
+        &mut self,
+        socket: &UdpSocket,
+        settings: &Settings,
+        now: Instant,
+    ) -> Result<(), String> {
+        self.register(socket, settings, now)
+    }
+
+    fn tick(&mut self, socket: &UdpSocket, settings: &Settings, now: Instant) -> Option<Instant> {
+        if now >= self.next_register {
+            if let Err(e) = self.register(socket, settings, now) {
+                error!("{}", e);
+            }
+        }
+        Some(self.next_register)
+    }
+
+    fn probe_port(&self) -> Option<u16> {
+        Some(self.probe_port)
+    }
+}
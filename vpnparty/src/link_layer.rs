@@ -0,0 +1,266 @@
+// SPDX-FileCopyrightText: 2026 Vladyslav Tsilytskyi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Link layer framing of captured and injected packets
+
+use pcap::{Active, Capture, Device, Linktype};
+
+use crate::packet::PacketError;
+use crate::warn;
+
+const ETHERNET_HEADER_LEN: usize = 14;
+const VLAN_TAG_LEN: usize = 4;
+const LINUX_SLL_HEADER_LEN: usize = 16;
+const LINUX_SLL2_HEADER_LEN: usize = 20;
+const NULL_HEADER_LEN: usize = 4;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88a8;
+
+/// BSD address family of IPv4, same value on every platform
+const AF_INET: u32 = 2;
+
+/// Address family of IPv6 differs between platforms. Npcap loopback uses BSD value.
+#[cfg(target_os = "linux")]
+const AF_INET6: u32 = 10;
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+const AF_INET6: u32 = 30;
+#[cfg(target_os = "freebsd")]
+const AF_INET6: u32 = 28;
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd"
+)))]
+const AF_INET6: u32 = 24;
+
+/// DLT_LOOP is used by OpenBSD, so its IPv6 address family is used
+const LOOP_AF_INET6: u32 = 24;
+
+/// Linktype 228 is IPv4 without any link layer header
+const LINKTYPE_IPV4: Linktype = Linktype(228);
+
+/// Offset of protocol field in Linux cooked capture headers
+const LINUX_SLL_PROTOCOL: usize = 14;
+const LINUX_SLL2_PROTOCOL: usize = 0;
+
+/// Link layer of the device, which packets are injected into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Link {
+    pub linktype: Linktype,
+    /// Source MAC of Ethernet frames. Injected frames go to the wire as they are.
+    pub mac: [u8; 6],
+}
+
+impl Link {
+    /// Link layer of the opened device
+    pub fn of(device: &Device, cap: &Capture<Active>) -> Link {
+        let linktype: Linktype = cap.get_datalink();
+        let mac: [u8; 6] = match (linktype, device_mac(device)) {
+            (_, Some(mac)) => mac,
+            (Linktype::ETHERNET, None) => {
+                warn!(
+                    "MAC address of {} is unknown, injected frames carry zero source MAC.",
+                    device.name
+                );
+                [0x00; 6]
+            }
+            _ => [0x00; 6],
+        };
+        Link { linktype, mac }
+    }
+}
+
+/// MAC address of the device. Npcap names devices after adapter GUID.
+fn device_mac(device: &Device) -> Option<[u8; 6]> {
+    let name: &str = device
+        .name
+        .strip_prefix("\\Device\\NPF_")
+        .unwrap_or(&device.name);
+    match mac_address::mac_address_by_name(name) {
+        Ok(mac) => mac.map(|m| m.bytes()),
+        Err(_) => None,
+    }
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> Result<u16, PacketError> {
+    match buf.get(offset..offset + 2) {
+        Some(b) => Ok(((b[0] as u16) << 8) + (b[1] as u16)),
+        None => Err(PacketError::Truncated {
+            expected: offset + 2,
+            actual: buf.len(),
+        }),
+    }
+}
+
+/// Skip link layer header of captured frame and return IP packet
+pub fn strip_link_header(linktype: Linktype, frame: &[u8]) -> Result<&[u8], PacketError> {
+    let offset: usize = match linktype {
+        Linktype::ETHERNET => {
+            // Skip 802.1Q and 802.1ad tags if any
+            let mut offset: usize = ETHERNET_HEADER_LEN - 2;
+            let mut ethertype: u16 = read_u16(frame, offset)?;
+            while ethertype == ETHERTYPE_VLAN || ethertype == ETHERTYPE_QINQ {
+                offset += VLAN_TAG_LEN;
+                ethertype = read_u16(frame, offset)?;
+            }
+            if ethertype != ETHERTYPE_IPV4 && ethertype != ETHERTYPE_IPV6 {
+                return Err(PacketError::NotIpEthertype(ethertype));
+            }
+            offset + 2
+        }
+        Linktype::LINUX_SLL | Linktype::LINUX_SLL2 => {
+            let (offset, header_len) = if linktype == Linktype::LINUX_SLL {
+                (LINUX_SLL_PROTOCOL, LINUX_SLL_HEADER_LEN)
+            } else {
+                (LINUX_SLL2_PROTOCOL, LINUX_SLL2_HEADER_LEN)
+            };
+            let protocol: u16 = read_u16(frame, offset)?;
+            if protocol != ETHERTYPE_IPV4 && protocol != ETHERTYPE_IPV6 {
+                return Err(PacketError::NotIpEthertype(protocol));
+            }
+            header_len
+        }
+        Linktype::NULL | Linktype::LOOP => NULL_HEADER_LEN,
+        Linktype::RAW | LINKTYPE_IPV4 => 0,
+        Linktype(other) => return Err(PacketError::UnsupportedLinktype(other)),
+    };
+
+    frame.get(offset..).ok_or(PacketError::Truncated {
+        expected: offset,
+        actual: frame.len(),
+    })
+}
+
+/// Check whether the IP packet can be injected into device with given linktype
+pub fn can_inject(linktype: Linktype) -> bool {
+    matches!(
+        linktype,
+        Linktype::ETHERNET | Linktype::NULL | Linktype::LOOP | Linktype::RAW | LINKTYPE_IPV4
+    )
+}
+
+/// Prepend link layer header required by the device to the IP packet
+pub fn frame_ip_packet(link: &Link, ip_packet: Vec<u8>) -> Result<Vec<u8>, PacketError> {
+    let is_ipv6: bool = ip_packet.first().map(|b| b >> 4) == Some(6);
+    let header: Vec<u8> = match link.linktype {
+        Linktype::RAW | LINKTYPE_IPV4 => return Ok(ip_packet),
+        // Destination MAC is unknown, so broadcast frame carries unicast IP packet
+        Linktype::ETHERNET => {
+            let mut h: Vec<u8> = vec![0xFF; 6];
+            h.extend_from_slice(&link.mac);
+            let ethertype: u16 = if is_ipv6 {
+                ETHERTYPE_IPV6
+            } else {
+                ETHERTYPE_IPV4
+            };
+            h.extend_from_slice(&ethertype.to_be_bytes());
+            h
+        }
+        // DLT_NULL uses host byte order, DLT_LOOP uses network byte order
+        Linktype::NULL if is_ipv6 => AF_INET6.to_ne_bytes().to_vec(),
+        Linktype::NULL => AF_INET.to_ne_bytes().to_vec(),
+        Linktype::LOOP if is_ipv6 => LOOP_AF_INET6.to_be_bytes().to_vec(),
+        Linktype::LOOP => AF_INET.to_be_bytes().to_vec(),
+        Linktype(other) => return Err(PacketError::UnsupportedLinktype(other)),
+    };
+
+    let mut frame: Vec<u8> = Vec::with_capacity(header.len() + ip_packet.len());
+    frame.extend_from_slice(&header);
+    frame.extend_from_slice(&ip_packet);
+    Ok(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IP: [u8; 4] = [0x45, 0x00, 0x00, 0x14];
+
+    #[test]
+    fn strip_ethernet() {
+        let mut frame: Vec<u8> = vec![0xFF; 12];
+        frame.extend_from_slice(&[0x08, 0x00]);
+        frame.extend_from_slice(&IP);
+        assert_eq!(strip_link_header(Linktype::ETHERNET, &frame), Ok(&IP[..]));
+
+        let mut tagged: Vec<u8> = vec![0xFF; 12];
+        tagged.extend_from_slice(&[0x81, 0x00, 0x00, 0x05, 0x08, 0x00]);
+        tagged.extend_from_slice(&IP);
+        assert_eq!(strip_link_header(Linktype::ETHERNET, &tagged), Ok(&IP[..]));
+
+        let mut arp: Vec<u8> = vec![0xFF; 12];
+        arp.extend_from_slice(&[0x08, 0x06]);
+        assert_eq!(
+            strip_link_header(Linktype::ETHERNET, &arp),
+            Err(PacketError::NotIpEthertype(0x0806))
+        );
+
+        assert!(strip_link_header(Linktype::ETHERNET, &[0xFF; 10]).is_err());
+    }
+
+    fn link(linktype: Linktype) -> Link {
+        Link {
+            linktype,
+            mac: [0x02, 0x00, 0x00, 0x00, 0x00, 0x01],
+        }
+    }
+
+    #[test]
+    fn strip_other_linktypes() {
+        let mut sll: Vec<u8> = vec![0x00; 14];
+        sll.extend_from_slice(&[0x08, 0x00]);
+        sll.extend_from_slice(&IP);
+        assert_eq!(strip_link_header(Linktype::LINUX_SLL, &sll), Ok(&IP[..]));
+        sll[14..16].copy_from_slice(&[0x08, 0x06]);
+        assert_eq!(
+            strip_link_header(Linktype::LINUX_SLL, &sll),
+            Err(PacketError::NotIpEthertype(0x0806))
+        );
+
+        let mut sll2: Vec<u8> = vec![0x86, 0xdd];
+        sll2.extend_from_slice(&[0x00; 18]);
+        sll2.extend_from_slice(&IP);
+        assert_eq!(strip_link_header(Linktype::LINUX_SLL2, &sll2), Ok(&IP[..]));
+        assert!(strip_link_header(Linktype::LINUX_SLL2, &sll2[18..]).is_err());
+
+        let mut null: Vec<u8> = vec![0x02, 0x00, 0x00, 0x00];
+        null.extend_from_slice(&IP);
+        assert_eq!(strip_link_header(Linktype::NULL, &null), Ok(&IP[..]));
+
+        assert_eq!(strip_link_header(Linktype::RAW, &IP), Ok(&IP[..]));
+        assert_eq!(
+            strip_link_header(Linktype::IEEE802_11, &IP),
+            Err(PacketError::UnsupportedLinktype(105))
+        );
+    }
+
+    #[test]
+    fn frame_for_injection() {
+        assert_eq!(
+            frame_ip_packet(&link(Linktype::RAW), IP.to_vec()),
+            Ok(IP.to_vec())
+        );
+
+        let eth: Vec<u8> = frame_ip_packet(&link(Linktype::ETHERNET), IP.to_vec()).unwrap();
+        assert_eq!(eth.len(), 18);
+        assert_eq!(eth[6..12], link(Linktype::ETHERNET).mac);
+        assert_eq!(strip_link_header(Linktype::ETHERNET, &eth), Ok(&IP[..]));
+
+        let lo: Vec<u8> = frame_ip_packet(&link(Linktype::LOOP), IP.to_vec()).unwrap();
+        assert_eq!(lo[..4], [0x00, 0x00, 0x00, 0x02]);
+
+        let ip6: [u8; 1] = [0x60];
+        let eth6: Vec<u8> = frame_ip_packet(&link(Linktype::ETHERNET), ip6.to_vec()).unwrap();
+        assert_eq!(eth6[12..14], [0x86, 0xdd]);
+        assert_eq!(strip_link_header(Linktype::ETHERNET, &eth6), Ok(&ip6[..]));
+        let lo6: Vec<u8> = frame_ip_packet(&link(Linktype::LOOP), ip6.to_vec()).unwrap();
+        assert_eq!(lo6[..4], [0x00, 0x00, 0x00, 0x18]);
+
+        assert!(!can_inject(Linktype::LINUX_SLL));
+        assert!(frame_ip_packet(&link(Linktype::LINUX_SLL), IP.to_vec()).is_err());
+    }
+}
@@ -0,0 +1,206 @@
+// SPDX-FileCopyrightText: 2026 Vladyslav Tsilytskyi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Track which buddies are still in the party
+
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::sync::mpsc::Sender;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use crate::auth::ReplayGuard;
+use crate::sup::{self, Kind, Message, Settings};
+use crate::{debug, e, error, Vpacket};
+
+/// Heartbeats are sent this often
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Buddy is gone after this long silence, several heartbeats must be lost in a row
+pub const PEER_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// What happened to the buddy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    /// First packet from the buddy or buddy returned after leaving
+    Joined,
+    /// Static buddy answers again
+    Reachable,
+    /// Dynamic buddy is removed from forwarding
+    Left,
+    /// Static buddy is silent, but packets are still forwarded
+    Unreachable,
+}
+
+/// Liveness of buddies of a single direction.
+/// Static buddies come from command line, they are never removed from forwarding.
+#[derive(Debug, Default)]
+pub struct Peers {
+    last_seen: HashMap<IpAddr, Instant>,
+    static_buddies: HashSet<IpAddr>,
+    unreachable: HashSet<IpAddr>,
+}
+
+impl Peers {
+    pub fn new(static_buddies: HashSet<IpAddr>) -> Peers {
+        Peers {
+            static_buddies,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_static(&self, ip: &IpAddr) -> bool {
+        self.static_buddies.contains(ip)
+    }
+
+    /// Buddies, which answered recently
+    pub fn alive(&self) -> impl Iterator<Item = &IpAddr> {
+        self.last_seen.keys()
+    }
+
+    /// Any packet from the buddy proves it is alive
+    pub fn seen(&mut self, ip: IpAddr, now: Instant) -> Option<Change> {
+        let is_new: bool = self.last_seen.insert(ip, now).is_none();
+        if self.unreachable.remove(&ip) {
+            return Some(Change::Reachable);
+        }
+        if is_new && !self.is_static(&ip) {
+            return Some(Change::Joined);
+        }
+        None
+    }
+
+    /// Buddy said goodbye
+    pub fn bye(&mut self, ip: IpAddr) -> Option<Change> {
+        self.last_seen.remove(&ip)?;
+        Some(self.gone(ip))
+    }
+
+    /// Forget buddies, which were silent for too long
+    pub fn expire(&mut self, now: Instant, timeout: Duration) -> Vec<(IpAddr, Change)> {
+        let silent: Vec<IpAddr> = self
+            .last_seen
+            .iter()
+            .filter(|(_, seen)| now.saturating_duration_since(**seen) > timeout)
+            .map(|(ip, _)| *ip)
+            .collect();
+        silent
+            .into_iter()
+            .map(|ip| {
+                self.last_seen.remove(&ip);
+                (ip, self.gone(ip))
+            })
+            .collect()
+    }
+
+    fn gone(&mut self, ip: IpAddr) -> Change {
+        if self.is_static(&ip) {
+            self.unreachable.insert(ip);
+            Change::Unreachable
+        } else {
+            Change::Left
+        }
+    }
+}
+
+/// Send short protocol message to every buddy on every given port
+pub fn notify(socket: &UdpSocket, buddies: &HashSet<IpAddr>, ports: &[u16], msg: &[u8]) {
+    for ip in buddies {
+        for port in ports {
+            if let Err(e) = socket.send_to(msg, SocketAddr::new(*ip, *port)) {
+                error!("Can't notify {} buddy: {}", ip, e);
+            }
+        }
+    }
+}
+
+/// Round trip time of VPN connection to the buddy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rtt {
+    pub direction_id: usize,
+    pub ip: IpAddr,
+    pub id: u64,
+    pub rtt: Duration,
+}
+
+/// Ping of heartbeat is time since start in microseconds, so pong tells the round trip time
+pub fn ping_stamp() -> u64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_micros() as u64
+}
+
+/// Receive pongs to heartbeats, which were sent from the socket
+pub fn listen_pongs(
+    direction_id: usize,
+    socket: UdpSocket,
+    tx: Sender<Vpacket>,
+    settings: Settings,
+) -> Result<(), String> {
+    let mut replay: ReplayGuard = ReplayGuard::default();
+    let mut buf = [0; sup::MAX_MESSAGE_LEN];
+    loop {
+        let (len, remote) = match socket.recv_from(&mut buf) {
+            Ok(p) => p,
+            // Windows reports ICMP port unreachable of previous heartbeat here
+            Err(e) if e.kind() == io::ErrorKind::ConnectionReset => continue,
+            Err(e) => return Err(e.to_string()),
+        };
+        let msg: Message = match Message::decode(&buf[..len]) {
+            Ok(m) if m.kind == Kind::Pong && m.party == settings.party => m,
+            _ => continue,
+        };
+        if let Err(e) = sup::authenticate(&settings, &mut replay, &msg, &buf[..len], remote.ip()) {
+            debug!("Rejected pong from {}: {}.", remote.ip(), e);
+            continue;
+        }
+        // Pong from the future is forged
+        let now: u64 = ping_stamp();
+        let Some(ping) = msg.ping.filter(|p| *p <= now) else {
+            continue;
+        };
+        e!(tx.send(Vpacket::R(Rtt {
+            direction_id,
+            ip: remote.ip(),
+            id: msg.sender,
+            rtt: Duration::from_micros(now - ping),
+        })));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dynamic_peer() {
+        let ip: IpAddr = [10, 0, 0, 2].into();
+        let t0 = Instant::now();
+        let mut p = Peers::default();
+        assert_eq!(p.seen(ip, t0), Some(Change::Joined));
+        assert_eq!(p.seen(ip, t0 + HEARTBEAT_INTERVAL), None);
+
+        assert!(p.expire(t0 + PEER_TIMEOUT, PEER_TIMEOUT).is_empty());
+        let gone = p.expire(t0 + HEARTBEAT_INTERVAL + PEER_TIMEOUT * 2, PEER_TIMEOUT);
+        assert_eq!(gone, [(ip, Change::Left)]);
+
+        assert_eq!(p.seen(ip, t0), Some(Change::Joined));
+        assert_eq!(p.bye(ip), Some(Change::Left));
+        assert_eq!(p.bye(ip), None);
+    }
+
+    #[test]
+    fn static_peer() {
+        let ip: IpAddr = [10, 0, 0, 3].into();
+        let t0 = Instant::now();
+        let mut p = Peers::new(HashSet::from([ip]));
+        // Static buddy may not run vpnparty at all, its silence is fine
+        assert!(p.expire(t0 + PEER_TIMEOUT * 10, PEER_TIMEOUT).is_empty());
+
+        assert_eq!(p.seen(ip, t0), None);
+        let gone = p.expire(t0 + PEER_TIMEOUT * 2, PEER_TIMEOUT);
+        assert_eq!(gone, [(ip, Change::Unreachable)]);
+        assert_eq!(p.seen(ip, t0), Some(Change::Reachable));
+        assert_eq!(p.bye(ip), Some(Change::Unreachable));
+    }
+}
@@ -1,63 +1,92 @@
 // SPDX-FileCopyrightText: 2026 Vladyslav Tsilytskyi
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+mod adapter_rules;
+mod broadcast_discovery;
 mod broadcast_listener;
+mod capture;
 mod cli_parser;
-mod logger;
+mod discovery;
+mod lighthouse_discovery;
+mod link_layer;
+mod liveness;
 mod mdns_listener;
 mod multicast_discovery;
 mod network_devices;
+mod packet;
 mod pcap_filter;
+mod rebroadcast;
+mod refresh;
+mod relay;
+mod roster;
+#[cfg(test)]
+mod test_devices;
+mod tunnel;
 mod udp;
 mod udp_discovery;
 
+use link_layer::Link;
 use pcap::{Active, Capture, Device};
-use std::collections::HashSet;
-use std::net::Ipv4Addr;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
 use std::str::FromStr;
-use std::sync::mpsc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
 use std::thread;
+use std::time::Instant;
 use std::vec::Vec;
+use vpnparty_protocol::{auth, logger, subnet, sup};
+use vpnparty_protocol::{critical, debug, e, error, info, trace, warn};
 
 const MULTICAST_IP: &str = "239.1.2.3";
+/// Organization-local scope, same as 239.0.0.0/8 for IPv4
+const MULTICAST_IP6: &str = "ff15::1:2:3";
 const MULTICAST_PORT: u16 = 54929;
 const UDPING_PORT: u16 = 54928;
 const MDNS_PORT: u16 = 54927;
-
-const SUP_LEN: usize = 6;
-const SUP: [u8; SUP_LEN] = [0x00, 0x01, 0x53, 0x75, 0x70, 0x21];
-const SUP_REPLY: [u8; 10] = [0x01, 0x53, 0x75, 0x70, 0x2c, 0x20, 0x62, 0x72, 0x6f, 0x21];
-
-// TODO:
-//   --version (app and Sup protocol)
+const LIGHTHOUSE_PORT: u16 = 54930;
+const BROADCAST_PORT: u16 = 54931;
+const TUNNEL_PORT: u16 = 54932;
 
 /// VPN device and related destination IPs
 struct Direction {
-    vpnip: Ipv4Addr,
-    // vpndevice: Device,
+    vpnip: IpAddr,
+    vpnnet: subnet::IpNet,
+    /// Interface index, used to join IPv6 multicast group
+    vpnifindex: u32,
+    vpndevice: Device,
     vpncap: Capture<Active>,
-    buddyip: HashSet<Ipv4Addr>,
+    vpnlink: Link,
+    /// Buddies, which receive forwarded packets
+    buddyip: HashSet<IpAddr>,
+    peers: liveness::Peers,
+    /// Networks to sweep by UDP discovery, own network if empty
+    scan: Vec<subnet::IpNet>,
+    /// Buddies, which accept forwarded packets via UDP tunnel, with their tunnel ports
+    tunnels: HashMap<IpAddr, u16>,
+    /// Buddies, which every buddy reaches directly, as it gossips them
+    reaches: HashMap<IpAddr, HashSet<IpAddr>>,
 }
 
 enum Vpacket {
-    /// Broadcast packet body
+    /// Broadcast IP packet without link layer header
     B(Vec<u8>),
 
-    /// mDNS packet body
+    /// mDNS IP packet without link layer header
     D(Vec<u8>),
 
-    /// IP address gathered via multicast
-    M((usize, Ipv4Addr)),
+    /// Buddy found or lost by peer discovery
+    P(discovery::Event),
 
-    /// IP address gathered via udping
-    U((usize, Ipv4Addr)),
-}
+    /// Buddy answered heartbeat
+    R(liveness::Rtt),
 
-/// Macro to cast any error type to String
-/// //TODO: verify
-#[macro_export]
-macro_rules! e {
-    ($($arg:tt)+) => ($($arg)+.map_err(|e| e.to_string())?)
+    /// Packet, which buddy forwarded to us
+    F(rebroadcast::Forwarded),
+
+    /// User stopped the application
+    Q,
 }
 
 fn main() -> Result<(), String> {
@@ -69,30 +98,51 @@ fn main() -> Result<(), String> {
 
     let broadcast_srcdev: Device = devices.src.clone();
     let mdns_srcdev: Device = devices.src.clone();
-    let mut vpn_ipv4_cap: Vec<Direction> =
-        network_devices::open_dst_devices(devices, &args.buddyip)?;
+    let lan_srcdev: Device = devices.src.clone();
+    let mut vpn_cap: Vec<Direction> = network_devices::open_dst_devices(
+        devices,
+        &args.buddyip,
+        &args.scan,
+        &args.netmask,
+        &args.vpnaddr,
+    )?;
 
     let (tx, rx) = mpsc::channel();
 
+    let settings = sup::Settings {
+        refresh: args.refresh,
+        secret: args.secret.clone(),
+        party: args.party.clone(),
+        lighthouse: args.lighthouse,
+        pps: args.pps,
+    };
+    info!("Joining the {}.", sup::party_name(&settings.party));
+
+    // Every mechanism runs in its own thread for every direction
+    let mut discoveries: Vec<(usize, Box<dyn discovery::Discovery>)> = Vec::new();
+
     // Init multicast peer discovery
     if !args.no_multicast {
         // Get multicast IP address and port
         let multicast_ip = args.mip.unwrap_or(e!(Ipv4Addr::from_str(MULTICAST_IP)));
+        let multicast_ip6 = args.mip6.unwrap_or(e!(Ipv6Addr::from_str(MULTICAST_IP6)));
         let multicast_port = args.mport.unwrap_or(MULTICAST_PORT);
 
         // Listen VPN devices for multicast discovery packets
-        for (direction_id, d) in vpn_ipv4_cap.iter().enumerate() {
-            let mtx = tx.clone();
-            let vpnip = d.vpnip;
-            let _multicast_handle = thread::spawn(move || {
-                let _ = multicast_discovery::run_multicast(
-                    direction_id,
-                    mtx,
-                    vpnip,
-                    multicast_ip,
+        for (direction_id, d) in vpn_cap.iter().enumerate() {
+            let group: IpAddr = match d.vpnip {
+                IpAddr::V4(_) => IpAddr::V4(multicast_ip),
+                IpAddr::V6(_) => IpAddr::V6(multicast_ip6),
+            };
+            discoveries.push((
+                direction_id,
+                Box::new(multicast_discovery::Multicast::new(
+                    d.vpnip,
+                    d.vpnifindex,
+                    group,
                     multicast_port,
-                );
-            });
+                )),
+            ));
         }
 
         info!("Multicast peer discovery initialized.");
@@ -102,22 +152,169 @@ fn main() -> Result<(), String> {
     if !args.no_udping {
         let udping_port = args.uport.unwrap_or(UDPING_PORT);
 
-        for (direction_id, d) in vpn_ipv4_cap.iter().enumerate() {
-            let utx = tx.clone();
-            let vpnip = d.vpnip;
-            let _udping_handle = thread::spawn(move || {
-                let _ = udp_discovery::run_udping(direction_id, utx, vpnip, udping_port);
-            });
+        for (direction_id, d) in vpn_cap.iter().enumerate() {
+            let buddies: Vec<IpAddr> = d.buddyip.iter().cloned().collect();
+            discoveries.push((
+                direction_id,
+                Box::new(udp_discovery::Udping::new(
+                    d.vpnnet,
+                    &buddies,
+                    d.scan.clone(),
+                    udping_port,
+                )),
+            ));
         }
 
         info!("UDP peer discovery initialized.");
     }
 
+    // Init directed broadcast peer discovery
+    if args.broadcast {
+        let broadcast_port = args.bport.unwrap_or(BROADCAST_PORT);
+
+        for (direction_id, d) in vpn_cap.iter().enumerate() {
+            // Point-to-point networks have no room for broadcast address
+            match d.vpnnet {
+                subnet::IpNet::V4(net) if net.prefix < 31 => discoveries.push((
+                    direction_id,
+                    Box::new(broadcast_discovery::SubnetBroadcast::new(
+                        net,
+                        broadcast_port,
+                    )),
+                )),
+                _ => debug!("No broadcast discovery in {} network.", d.vpnnet),
+            }
+        }
+
+        info!("Broadcast peer discovery initialized.");
+    }
+
+    // Register at lighthouse
+    if let Some(lighthouse) = args.lighthouse {
+        // Buddies from lighthouse are greeted at the port they listen Sup on
+        let probe_port: u16 = if args.no_udping {
+            args.mport.unwrap_or(MULTICAST_PORT)
+        } else {
+            args.uport.unwrap_or(UDPING_PORT)
+        };
+
+        for (direction_id, d) in vpn_cap.iter().enumerate() {
+            if d.vpnip.is_ipv4() != lighthouse.is_ipv4() {
+                continue;
+            }
+            discoveries.push((
+                direction_id,
+                Box::new(lighthouse_discovery::Lighthouse::new(
+                    d.vpnnet, lighthouse, probe_port,
+                )),
+            ));
+        }
+
+        info!("Lighthouse peer discovery initialized.");
+    }
+
+    let stop_discovery: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+    let discovery_handles: Vec<thread::JoinHandle<()>> = discoveries
+        .into_iter()
+        .map(|(direction_id, d)| {
+            discovery::spawn(
+                direction_id,
+                vpn_cap[direction_id].vpnnet,
+                tx.clone(),
+                d,
+                settings.clone(),
+                stop_discovery.clone(),
+            )
+        })
+        .collect();
+
+    // Forward packets via UDP tunnel to buddies, who support it
+    let tunnel_sockets: Vec<Option<UdpSocket>> = vpn_cap
+        .iter()
+        .map(|d| {
+            if !args.tunnel.iter().any(|net| net.contains(&d.vpnip)) {
+                return None;
+            }
+            match UdpSocket::bind(SocketAddr::new(d.vpnip, TUNNEL_PORT)) {
+                Ok(s) => Some(s),
+                Err(e) => {
+                    warn!("UDP tunnel on {} is disabled: {}", d.vpnip, e);
+                    None
+                }
+            }
+        })
+        .collect();
+    for (direction_id, socket) in tunnel_sockets.iter().enumerate() {
+        let Some(socket) = socket else {
+            continue;
+        };
+        let socket: UdpSocket = e!(socket.try_clone());
+        let ttx = tx.clone();
+        let secret: Option<auth::Secret> = settings.secret.clone();
+        let ports: Vec<u16> = args.port.clone();
+        thread::spawn(move || {
+            if let Err(e) = tunnel::listen(direction_id, socket, ttx, secret, ports) {
+                error!("UDP tunnel stopped: {}", e);
+            }
+        });
+    }
+    let has_tunnel: bool = tunnel_sockets.iter().any(|s| s.is_some());
+    if has_tunnel {
+        info!("UDP tunnel initialized.");
+    }
+
+    // Packets from tunnel and re-broadcast packets are delivered to this host via loopback
+    let mut loopback: Option<(Capture<Active>, Link)> = None;
+    if has_tunnel || args.rebroadcast.is_some() {
+        loopback = Some(rebroadcast::open_loopback()?);
+    }
+
+    // Computers of LAN without vpnparty hear buddies too
+    let mut lan: Option<(Capture<Active>, Link)> = None;
+    if args.lan {
+        require_tunnel6("--lan", &vpn_cap, &tunnel_sockets)?;
+        lan = Some(rebroadcast::open_injection(lan_srcdev)?);
+    }
+    if args.bridge {
+        require_tunnel6("--bridge", &vpn_cap, &tunnel_sockets)?;
+    }
+    if args.relay.is_some() {
+        require_tunnel6("--relay", &vpn_cap, &tunnel_sockets)?;
+    }
+
+    // Capture packets forwarded by buddies to turn them into broadcasts or relay them
+    if args.rebroadcast.is_some() || args.lan || args.bridge || args.relay.is_some() {
+        for (direction_id, d) in vpn_cap.iter().enumerate() {
+            let IpAddr::V4(vpnip) = d.vpnip else {
+                continue;
+            };
+            let ftx = tx.clone();
+            let vpndevice: Device = d.vpndevice.clone();
+            // mDNS of buddies is forwarded to its own port
+            let mut ports: Vec<u16> = args.port.clone();
+            if !ports.is_empty() {
+                ports.push(MDNS_PORT);
+            }
+            thread::spawn(move || {
+                if let Err(e) =
+                    rebroadcast::listen_forwarded(vpndevice, vpnip, direction_id, ftx, &ports)
+                {
+                    error!("Capture of forwarded packets on {} stopped: {}", vpnip, e);
+                }
+            });
+        }
+
+        info!("Re-broadcast of forwarded packets initialized.");
+    }
+
     // Capture game-related broadcast packets
     {
         let btx = tx.clone();
         let _broadcast_handle = thread::spawn(move || {
-            let _ = broadcast_listener::listen_broadcast(broadcast_srcdev, btx, &args.port);
+            if let Err(e) = broadcast_listener::listen_broadcast(broadcast_srcdev, btx, &args.port)
+            {
+                critical!("Broadcast listener stopped: {}", e);
+            }
         });
 
         info!("Broadcast listener initialized.");
@@ -127,18 +324,121 @@ fn main() -> Result<(), String> {
     {
         let mtx = tx.clone();
         let _mdns_handle = thread::spawn(move || {
-            let _ = mdns_listener::listen_mdns(mdns_srcdev, mtx);
+            if let Err(e) = mdns_listener::listen_mdns(mdns_srcdev, mtx) {
+                critical!("mDNS listener stopped: {}", e);
+            }
         });
 
         info!("mDNS listener initialized.");
     }
 
-    // TODO: add refresh!
+    // Say goodbye to buddies on Ctrl+C
+    {
+        let qtx = tx.clone();
+        e!(ctrlc::set_handler(move || {
+            let _ = qtx.send(Vpacket::Q);
+        }));
+    }
+
+    // Heartbeats go to every port where buddies listen for Sup protocol
+    let mut sup_ports: Vec<u16> = Vec::new();
+    if !args.no_udping {
+        sup_ports.push(args.uport.unwrap_or(UDPING_PORT));
+    }
+    if !args.no_multicast {
+        sup_ports.push(args.mport.unwrap_or(MULTICAST_PORT));
+    }
+    if args.broadcast {
+        sup_ports.push(args.bport.unwrap_or(BROADCAST_PORT));
+    }
+    let sup_sockets: Vec<Option<UdpSocket>> = vpn_cap
+        .iter()
+        .map(|d| match UdpSocket::bind(SocketAddr::new(d.vpnip, 0)) {
+            Ok(s) => Some(s),
+            Err(e) => {
+                warn!("Heartbeats from {} are disabled: {}", d.vpnip, e);
+                None
+            }
+        })
+        .collect();
+    for (direction_id, socket) in sup_sockets.iter().enumerate() {
+        let Some(socket) = socket else {
+            continue;
+        };
+        let socket: UdpSocket = match socket.try_clone() {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("Round trip time is not measured: {}", e);
+                continue;
+            }
+        };
+        let rtx = tx.clone();
+        let rsettings: sup::Settings = settings.clone();
+        thread::spawn(move || {
+            if let Err(e) = liveness::listen_pongs(direction_id, socket, rtx, rsettings) {
+                error!("Round trip time is not measured anymore: {}", e);
+            }
+        });
+    }
+
+    // Buddy on several VPNs gets packets via a single path
+    let priorities: Vec<usize> = vpn_cap
+        .iter()
+        .map(|d| {
+            args.prefer
+                .iter()
+                .position(|net| net.contains(&d.vpnip))
+                .unwrap_or(args.prefer.len())
+        })
+        .collect();
+    let mut roster: roster::Roster = roster::Roster::new(priorities);
+
+    let heartbeat: sup::Message = sup::Message::new(sup::Kind::Heartbeat);
+    let bye: sup::Message = sup::Message::new(sup::Kind::Bye);
+    let mut next_heartbeat: Instant = Instant::now();
+    let mut recent: relay::Recent = relay::Recent::default();
 
     // No panics, unwraps or "?" in this loop. Report failures and proceed to next packet.
     loop {
-        let packet: Vpacket = match rx.recv() {
+        let now: Instant = Instant::now();
+        if now >= next_heartbeat {
+            let mut departures: Vec<(roster::Path, liveness::Change)> = Vec::new();
+            for (direction_id, (d, socket)) in vpn_cap.iter_mut().zip(&sup_sockets).enumerate() {
+                if let Some(socket) = socket {
+                    // Backup paths get heartbeats too, so they stay alive
+                    let targets: HashSet<IpAddr> = d
+                        .buddyip
+                        .union(&d.peers.alive().cloned().collect())
+                        .cloned()
+                        .collect();
+                    // Heartbeat tells buddies about each other. Only buddies heard recently
+                    // are reached for sure, silent static buddies are left out.
+                    let gossip = sup::Gossip {
+                        net: d.vpnnet,
+                        peers: d.peers.alive().cloned().collect(),
+                    };
+                    let msg: Vec<u8> = sup::Message {
+                        gossip: Some(gossip),
+                        ping: Some(liveness::ping_stamp()),
+                        tunnel: tunnel_sockets[direction_id].as_ref().map(|_| TUNNEL_PORT),
+                        ..heartbeat.clone()
+                    }
+                    .encode_for(d.vpnip, &settings);
+                    liveness::notify(socket, &targets, &sup_ports, &msg);
+                }
+                for (ip, change) in d.peers.expire(now, liveness::PEER_TIMEOUT) {
+                    departures.push((roster::Path { direction_id, ip }, change));
+                }
+            }
+            for (path, change) in departures {
+                report_departure(&mut vpn_cap, &mut roster, path, change);
+            }
+            next_heartbeat = now + liveness::HEARTBEAT_INTERVAL;
+        }
+
+        let packet: Vpacket = match rx.recv_timeout(next_heartbeat.saturating_duration_since(now)) {
             Ok(p) => p,
+            Err(RecvTimeoutError::Timeout) => continue,
             Err(e) => {
                 error!("Can't receive a packet: {}", e);
                 continue;
@@ -146,65 +446,438 @@ fn main() -> Result<(), String> {
         };
 
         match packet {
-            Vpacket::B(data) => {
-                // Start from 14th byte to skip Ethernet Frame.
-                // let no_eth_packet_len = data.len() - 14;
-                for d in &mut vpn_ipv4_cap {
+            Vpacket::B(ip_packet) => {
+                if let Err(e) = packet::validate_udp(&ip_packet) {
+                    debug!("Skipping malformed broadcast packet: {}", e);
+                    continue;
+                }
+                for (d, tunnel_socket) in vpn_cap.iter_mut().zip(&tunnel_sockets) {
+                    let buddies: Vec<IpAddr> = d.buddyip.iter().cloned().collect();
+                    send_to_buddies(d, tunnel_socket, &buddies, &ip_packet, 0, &settings.secret);
+                }
+            }
+            Vpacket::D(ip_packet) => {
+                if let Err(e) = packet::parse_udp4(&ip_packet) {
+                    debug!("Skipping malformed mDNS packet: {}", e);
+                    continue;
+                }
+                for d in &mut vpn_cap {
+                    let IpAddr::V4(vpnip) = d.vpnip else {
+                        continue;
+                    };
                     for dstip in &d.buddyip {
-                        // TODO: send via LAN as well!
-                        let no_ether_pktbuf: Vec<u8> = udp::craft_udp_packet(
-                            &data[14..],
-                            &d.vpnip.octets(),
+                        let IpAddr::V4(dstip) = dstip else {
+                            continue;
+                        };
+                        let no_ether_pktbuf: Vec<u8> = match udp::craft_udp_packet(
+                            &ip_packet,
+                            &vpnip.octets(),
                             &dstip.octets(),
-                            None,
-                            None,
-                        );
+                            Some(MDNS_PORT),
+                            Some(rebroadcast::mark(0)),
+                        ) {
+                            Ok(p) => p,
+                            Err(e) => {
+                                error!("Can't craft mDNS packet: {}", e);
+                                continue;
+                            }
+                        };
+
+                        trace!("D {:?}", no_ether_pktbuf);
 
-                        trace!("B {:?}", no_ether_pktbuf);
+                        let frame: Vec<u8> =
+                            match link_layer::frame_ip_packet(&d.vpnlink, no_ether_pktbuf) {
+                                Ok(f) => f,
+                                Err(e) => {
+                                    error!("Can't frame mDNS packet: {}", e);
+                                    continue;
+                                }
+                            };
 
-                        if let Err(e) = d.vpncap.sendpacket(&*no_ether_pktbuf) {
+                        if let Err(e) = d.vpncap.sendpacket(&*frame) {
                             error!("Error while resending packet: {}", e);
                         }
                     }
                 }
             }
-            Vpacket::D(data) => {
-                // Start from 14th byte to skip Ethernet Frame.
-                // let no_eth_packet_len = data.len() - 14;
-                for d in &mut vpn_ipv4_cap {
-                    for dstip in &d.buddyip {
-                        // TODO: send via LAN as well!
-                        let no_ether_pktbuf: Vec<u8> = udp::craft_udp_packet(
-                            &data[14..],
-                            &d.vpnip.octets(),
-                            &dstip.octets(),
-                            Some(MDNS_PORT),
-                            None,
-                        );
+            Vpacket::F(fwd) => {
+                let d: &Direction = &vpn_cap[fwd.direction_id];
+                let src: IpAddr = match rebroadcast::source(&fwd.packet) {
+                    Ok(ip) => ip,
+                    Err(e) => {
+                        debug!("Skipping malformed forwarded packet: {}", e);
+                        continue;
+                    }
+                };
+                // Strangers on the VPN can't broadcast on this host
+                if !d.buddyip.contains(&src) && !d.peers.alive().any(|ip| *ip == src) {
+                    debug!(
+                        "Skipping forwarded packet from {}, who is not a buddy.",
+                        src
+                    );
+                    continue;
+                }
 
-                        trace!("D {:?}", no_ether_pktbuf);
+                // mDNS reaches this host directly, only LAN needs a copy. It isn't bridged or relayed.
+                if !fwd.tunneled && rebroadcast::dst_port(&fwd.packet) == Ok(MDNS_PORT) {
+                    if let Some((cap, link)) = &mut lan {
+                        match rebroadcast::craft_lan_mdns(&fwd.packet)
+                            .and_then(|p| link_layer::frame_ip_packet(link, p))
+                        {
+                            Ok(frame) => {
+                                trace!("N {:?}", frame);
+                                if let Err(e) = cap.sendpacket(&*frame) {
+                                    error!("Error while sending mDNS of buddy to LAN: {}", e);
+                                }
+                            }
+                            Err(e) => error!("Can't craft LAN mDNS packet: {}", e),
+                        }
+                    }
+                    continue;
+                }
 
-                        if let Err(e) = d.vpncap.sendpacket(&*no_ether_pktbuf) {
-                            error!("Error while resending packet: {}", e);
+                // Computers of LAN get a broadcast, it's never captured back due to the mark
+                if let Some((cap, link)) = &mut lan {
+                    match rebroadcast::craft_lan_broadcast(&fwd.packet, fwd.hops)
+                        .and_then(|p| link_layer::frame_ip_packet(link, p))
+                    {
+                        Ok(frame) => {
+                            trace!("N {:?}", frame);
+                            if let Err(e) = cap.sendpacket(&*frame) {
+                                error!("Error while sending forwarded packet to LAN: {}", e);
+                            }
                         }
+                        Err(e) => error!("Can't craft LAN broadcast: {}", e),
                     }
                 }
+
+                // Buddies of other VPNs hear the buddy too. Relayed packets are not relayed again,
+                // so two bridges between the same VPNs don't loop.
+                if args.bridge && fwd.hops == 0 {
+                    let origin: Option<u64> = roster.id(&roster::Path {
+                        direction_id: fwd.direction_id,
+                        ip: src,
+                    });
+                    for (direction_id, (d, tunnel_socket)) in
+                        vpn_cap.iter_mut().zip(&tunnel_sockets).enumerate()
+                    {
+                        if direction_id == fwd.direction_id {
+                            continue;
+                        }
+                        // Buddy on both VPNs has the packet already
+                        let buddies: Vec<IpAddr> = d
+                            .buddyip
+                            .iter()
+                            .filter(|ip| {
+                                origin.is_none()
+                                    || roster.id(&roster::Path {
+                                        direction_id,
+                                        ip: **ip,
+                                    }) != origin
+                            })
+                            .cloned()
+                            .collect();
+                        send_to_buddies(
+                            d,
+                            tunnel_socket,
+                            &buddies,
+                            &fwd.packet,
+                            1,
+                            &settings.secret,
+                        );
+                    }
+                }
+
+                // Buddies, who can't reach the buddy, get the packet from us. Copies coming
+                // around a loop are stopped by the hop limit.
+                if args.relay.is_some_and(|limit| fwd.hops < limit) {
+                    let d: &mut Direction = &mut vpn_cap[fwd.direction_id];
+                    // Nobody knows who needs the packet, until the buddy gossips
+                    let buddies: Vec<IpAddr> = match d.reaches.get(&src) {
+                        Some(reached) => d
+                            .buddyip
+                            .iter()
+                            .filter(|ip| **ip != src && !reached.contains(ip))
+                            .cloned()
+                            .collect(),
+                        None => Vec::new(),
+                    };
+                    if !buddies.is_empty() {
+                        match recent.is_new(fwd.direction_id, &fwd.packet, Instant::now()) {
+                            Ok(true) => send_to_buddies(
+                                d,
+                                &tunnel_sockets[fwd.direction_id],
+                                &buddies,
+                                &fwd.packet,
+                                fwd.hops + 1,
+                                &settings.secret,
+                            ),
+                            Ok(false) => trace!("Packet from {} is relayed already.", src),
+                            Err(e) => debug!("Skipping relay of malformed packet: {}", e),
+                        }
+                    }
+                }
+
+                // Host has received injected packet already, unless it must be a broadcast
+                if !fwd.tunneled && args.rebroadcast.is_none() {
+                    continue;
+                }
+                let Some((cap, link)) = &mut loopback else {
+                    continue;
+                };
+                // Packets from tunnel are delivered as unicast, unless re-broadcast is enabled
+                let crafted = match (args.rebroadcast, src) {
+                    (Some(dst), IpAddr::V4(_)) => rebroadcast::craft_broadcast(&fwd.packet, dst),
+                    _ => Ok(fwd.packet),
+                };
+                let frame: Vec<u8> =
+                    match crafted.and_then(|p| link_layer::frame_ip_packet(link, p)) {
+                        Ok(f) => f,
+                        Err(e) => {
+                            error!("Can't craft forwarded packet: {}", e);
+                            continue;
+                        }
+                    };
+
+                trace!("F {:?}", frame);
+
+                if let Err(e) = cap.sendpacket(&*frame) {
+                    error!("Error while delivering forwarded packet: {}", e);
+                }
             }
-            Vpacket::M((direction_id, sup_ip)) => {
-                let is_new = vpn_ipv4_cap[direction_id].buddyip.insert(sup_ip);
-                if is_new {
-                    info!("{} joined the party!", sup_ip);
+            Vpacket::P(event) => match event.peer {
+                discovery::Peer::Alive(sup_ip) => {
+                    let path = roster::Path {
+                        direction_id: event.direction_id,
+                        ip: sup_ip,
+                    };
+                    report_arrival(&mut vpn_cap, &mut roster, path, event.id);
+                    let d: &mut Direction = &mut vpn_cap[path.direction_id];
+                    // Heartbeat without full gossip leaves unknown, whom the buddy reaches
+                    if let Some(reaches) = event.reaches {
+                        d.reaches.insert(sup_ip, reaches.into_iter().collect());
+                    } else if event.heartbeat {
+                        d.reaches.remove(&sup_ip);
+                    }
+                    // Every heartbeat tells the tunnel port, buddy may stop accepting packets
+                    if let Some(port) = event.tunnel {
+                        if d.tunnels.insert(sup_ip, port).is_none() {
+                            debug!(
+                                "{} accepts packets via UDP tunnel on port {}.",
+                                sup_ip, port
+                            );
+                        }
+                    } else if event.heartbeat && d.tunnels.remove(&sup_ip).is_some() {
+                        debug!("{} doesn't accept packets via UDP tunnel anymore.", sup_ip);
+                    }
+                    trace!("{} {}", event.mechanism.letter(), sup_ip);
                 }
-                trace!("M {}", sup_ip);
+                discovery::Peer::Bye(bye_ip) => {
+                    let path = roster::Path {
+                        direction_id: event.direction_id,
+                        ip: bye_ip,
+                    };
+                    if let Some(change) = vpn_cap[path.direction_id].peers.bye(bye_ip) {
+                        report_departure(&mut vpn_cap, &mut roster, path, change);
+                    }
+                    trace!("L {}", bye_ip);
+                }
+            },
+            Vpacket::R(pong) => {
+                let path = roster::Path {
+                    direction_id: pong.direction_id,
+                    ip: pong.ip,
+                };
+                report_arrival(&mut vpn_cap, &mut roster, path, Some(pong.id));
+                if let Some(routes) = roster.rtt(path, pong.rtt) {
+                    apply_routes(&mut vpn_cap, &routes);
+                }
+                trace!("R {} {:?}", pong.ip, pong.rtt);
             }
-            Vpacket::U((direction_id, sup_ip)) => {
-                let is_new = vpn_ipv4_cap[direction_id].buddyip.insert(sup_ip);
-                if is_new {
-                    info!("{} joined the party!", sup_ip);
+            Vpacket::Q => {
+                for (d, socket) in vpn_cap.iter().zip(&sup_sockets) {
+                    if let Some(socket) = socket {
+                        let targets: HashSet<IpAddr> = d
+                            .buddyip
+                            .union(&d.peers.alive().cloned().collect())
+                            .cloned()
+                            .collect();
+                        let msg: Vec<u8> = bye.encode_for(d.vpnip, &settings);
+                        liveness::notify(socket, &targets, &sup_ports, &msg);
+                    }
+                }
+                // Multicast groups are left before exit
+                stop_discovery.store(true, Ordering::Relaxed);
+                for h in discovery_handles {
+                    let _ = h.join();
                 }
-                trace!("U {}", sup_ip);
+                info!("Party is over, bye!");
+                return Ok(());
             }
         }
     }
-    // e!(broadcast_handle.join());
+}
+
+/// Injected IPv6 packets carry no forward mark, only tunnel tells them apart
+fn require_tunnel6(
+    flag: &str,
+    vpn_cap: &[Direction],
+    tunnel_sockets: &[Option<UdpSocket>],
+) -> Result<(), String> {
+    for (d, tunnel_socket) in vpn_cap.iter().zip(tunnel_sockets) {
+        if d.vpnip.is_ipv6() && tunnel_socket.is_none() {
+            return Err(format!(
+                "{} needs --tunnel for IPv6 address {}, forwarded IPv6 packets can't be recognized otherwise.",
+                flag, d.vpnip
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Send game packet to buddies of the direction. Buddies, which support tunnel,
+/// get it via tunnel, others get crafted packet injected into VPN device.
+fn send_to_buddies(
+    d: &mut Direction,
+    tunnel_socket: &Option<UdpSocket>,
+    buddies: &[IpAddr],
+    ip_packet: &[u8],
+    hops: u8,
+    secret: &Option<auth::Secret>,
+) {
+    let frame: Option<tunnel::Frame> = match tunnel_socket {
+        Some(_) => tunnel::Frame::from_ip_packet(ip_packet, hops).ok(),
+        None => None,
+    };
+    for dstip in buddies {
+        // Buddy, which supports tunnel, gets the packet without injection
+        if let (Some(socket), Some(port), Some(frame)) =
+            (tunnel_socket, d.tunnels.get(dstip), &frame)
+        {
+            let datagram: Vec<u8> = frame.encode_for(d.vpnip, secret);
+            trace!("T {:?}", datagram);
+            if let Err(e) = socket.send_to(&datagram, SocketAddr::new(*dstip, *port)) {
+                error!("Error while sending packet via tunnel: {}", e);
+            }
+            continue;
+        }
+        let crafted = match (d.vpnip, dstip, ip_packet[0] >> 4) {
+            // Mark tells the buddy to re-broadcast the packet
+            (IpAddr::V4(src), IpAddr::V4(dst), 4) => udp::craft_udp_packet(
+                ip_packet,
+                &src.octets(),
+                &dst.octets(),
+                None,
+                Some(rebroadcast::mark(hops)),
+            ),
+            (IpAddr::V6(src), IpAddr::V6(dst), 6) => {
+                udp::craft_udp6_packet(ip_packet, &src.octets(), &dst.octets(), None)
+            }
+            // Packets are not translated between IP versions
+            _ => continue,
+        };
+        let no_ether_pktbuf: Vec<u8> = match crafted {
+            Ok(p) => p,
+            Err(e) => {
+                error!("Can't craft broadcast packet: {}", e);
+                continue;
+            }
+        };
+
+        trace!("B {:?}", no_ether_pktbuf);
+
+        let frame: Vec<u8> = match link_layer::frame_ip_packet(&d.vpnlink, no_ether_pktbuf) {
+            Ok(f) => f,
+            Err(e) => {
+                error!("Can't frame broadcast packet: {}", e);
+                continue;
+            }
+        };
+
+        if let Err(e) = d.vpncap.sendpacket(&*frame) {
+            error!("Error while resending packet: {}", e);
+        }
+    }
+}
+
+/// Buddy is alive, make sure packets are forwarded to it.
+/// Buddy with known ID gets packets only via its preferred path.
+fn report_arrival(
+    dirs: &mut [Direction],
+    roster: &mut roster::Roster,
+    path: roster::Path,
+    id: Option<u64>,
+) {
+    let ip: IpAddr = path.ip;
+    let change: Option<liveness::Change> = dirs[path.direction_id].peers.seen(ip, Instant::now());
+    let Some(id) = id else {
+        // Legacy buddy can't be told apart on several VPNs
+        match change {
+            Some(liveness::Change::Reachable) => info!("{} is reachable again.", ip),
+            _ if dirs[path.direction_id].buddyip.insert(ip) => info!("{} joined the party!", ip),
+            _ => {}
+        }
+        return;
+    };
+    let is_new_path: bool = roster.id(&path) != Some(id);
+    let is_new_buddy: bool = !roster.is_known(id);
+    for routes in roster.seen(id, path) {
+        apply_routes(dirs, &routes);
+    }
+    match change {
+        Some(liveness::Change::Reachable) => info!("{} is reachable again.", ip),
+        _ if is_new_path && is_new_buddy => info!("{} joined the party!", ip),
+        _ if is_new_path => info!(
+            "{} is another address of a buddy, which is already in the party.",
+            ip
+        ),
+        _ => {}
+    }
+}
+
+/// Buddy is silent or said goodbye. Static buddies keep receiving packets,
+/// unless the buddy is reachable via another path.
+fn report_departure(
+    dirs: &mut [Direction],
+    roster: &mut roster::Roster,
+    path: roster::Path,
+    change: liveness::Change,
+) {
+    let ip: IpAddr = path.ip;
+    dirs[path.direction_id].tunnels.remove(&ip);
+    dirs[path.direction_id].reaches.remove(&ip);
+    if change == liveness::Change::Left {
+        dirs[path.direction_id].buddyip.remove(&ip);
+    }
+    let preferred: Option<roster::Path> = roster.lost(path).and_then(|routes| {
+        apply_routes(dirs, &routes);
+        routes.preferred
+    });
+    match (preferred, change) {
+        (Some(p), _) => info!("{} is gone, buddy is reached via {} now.", ip, p.ip),
+        (None, liveness::Change::Unreachable) => {
+            warn!(
+                "{} is unreachable, but packets are still sent as it was given via -b.",
+                ip
+            )
+        }
+        (None, _) => info!("{} left the party.", ip),
+    }
+}
+
+/// Forward packets to the preferred path of the buddy only. Static buddies keep
+/// receiving packets until buddy's ID is known.
+fn apply_routes(dirs: &mut [Direction], routes: &roster::Routes) {
+    for p in &routes.paths {
+        let d: &mut Direction = &mut dirs[p.direction_id];
+        let forward: bool = match routes.preferred {
+            Some(preferred) => preferred == *p,
+            None => d.peers.is_static(&p.ip),
+        };
+        if !forward {
+            d.buddyip.remove(&p.ip);
+        } else if d.buddyip.insert(p.ip) && routes.paths.len() > 1 {
+            debug!("Buddy {:x} is reached via {} now.", routes.id, p.ip);
+        }
+    }
 }
@@ -3,46 +3,30 @@
 
 use std::sync::mpsc::Sender;
 
-use pcap::{Device, Packet};
+use pcap::Device;
 
+use crate::capture::capture_ip_packets;
 use crate::pcap_filter::host_filter;
+use crate::rebroadcast::FORWARD_MARK;
 use crate::{debug, e, error, Vpacket};
 
 pub fn listen_mdns(srcdev: Device, btx: Sender<Vpacket>) -> Result<(), String> {
-    let host_filter: String = host_filter(&srcdev.addresses);
+    let host_filter: String = host_filter(&srcdev.addresses)?;
+    // mDNS of buddies, which was sent to LAN, is not sent back
     let mdns_filter: String = format!(
-        "ip and {} and (dst host 224.0.0.251) and (udp dst port 5353)",
-        host_filter
+        "ip and {} and not ((ip[4:2] & 0xfff0) = {}) and (dst host 224.0.0.251) and (udp dst port 5353)",
+        host_filter, FORWARD_MARK
     );
     debug!("mDNS filter: {}", mdns_filter);
 
-    // Setup Capture
-    let mut hw_cap = e!(e!(pcap::Capture::from_device(srcdev))
-        .immediate_mode(false)
-        .timeout(571) // This is a workaround, because immediate mode doesn't work in Win11 build
-        .open());
-
-    e!(hw_cap.filter(mdns_filter.as_str(), true));
-
-    // TODO: make a breaking condition
-    loop {
-        let p = hw_cap.next_packet();
-        let packet: Packet = match p {
-            Ok(p) => p,
-            Err(e) => {
-                let pcap::Error::TimeoutExpired = e else {
-                    error!("Error while receiving mDNS packet: {}", e);
-                    continue;
-                };
-                continue;
-            }
-        };
-
-        if packet.header.len <= 42 {
+    capture_ip_packets(srcdev, &mdns_filter, 571, "mDNS", |ip_packet| {
+        // IPv4 and UDP headers without payload
+        if ip_packet.len() <= 28 {
             error!("This packet is empty, skipping.");
-            continue;
+            return Ok(());
         }
 
-        e!(btx.send(Vpacket::D(packet.data.to_vec())));
-    }
+        e!(btx.send(Vpacket::D(ip_packet.to_vec())));
+        Ok(())
+    })
 }
@@ -1,20 +1,36 @@
 // SPDX-FileCopyrightText: 2026 Vladyslav Tsilytskyi
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
-use std::sync::mpsc::Sender;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::time::Instant;
 
-use crate::{debug, e, error, Vpacket, SUP, SUP_LEN, SUP_REPLY};
+use crate::discovery::{Discovery, Mechanism};
+use crate::sup::{self, Settings};
+use crate::{debug, e};
 
+/// Join multicast group on VPN interface.
+/// IPv6 group is joined by interface index, IPv4 group by interface address.
 fn join_multicast_group(
-    src_addr: &Ipv4Addr,
-    m_addr: &Ipv4Addr,
+    src_addr: &IpAddr,
+    src_ifindex: u32,
+    m_addr: &IpAddr,
     m_port: u16,
 ) -> Result<UdpSocket, String> {
-    assert!(m_addr.is_multicast());
-    let vpn_socket = SocketAddrV4::new(*src_addr, m_port);
+    if !m_addr.is_multicast() {
+        return Err(format!("{} is not a multicast address.", m_addr));
+    }
+    let vpn_socket = SocketAddr::new(*src_addr, m_port);
     let udp_socket = e!(UdpSocket::bind(vpn_socket));
-    e!(udp_socket.join_multicast_v4(m_addr, src_addr));
+    match (src_addr, m_addr) {
+        (IpAddr::V4(src), IpAddr::V4(m)) => e!(udp_socket.join_multicast_v4(m, src)),
+        (IpAddr::V6(_), IpAddr::V6(m)) => e!(udp_socket.join_multicast_v6(m, src_ifindex)),
+        _ => {
+            return Err(format!(
+                "Multicast address {} and interface address {} are of different families.",
+                m_addr, src_addr
+            ))
+        }
+    }
     debug!(
         "Join multicast at address {}:{} on interface {}",
         m_addr, m_port, src_addr
@@ -23,55 +39,59 @@ fn join_multicast_group(
 }
 
 /// Peer discovery via multicast
-pub fn run_multicast(
-    direction_id: usize,
-    btx: Sender<Vpacket>,
-    src_ip: Ipv4Addr,
-    multicast_ip: Ipv4Addr,
-    multicast_port: u16,
-) -> Result<(), String> {
-    let listener: UdpSocket = join_multicast_group(&src_ip, &multicast_ip, multicast_port)?;
-    e!(listener.send_to(
-        &SUP,
-        SocketAddr::new(IpAddr::V4(multicast_ip), multicast_port)
-    ));
-
-    let mut buf = [0; 100];
-
-    // TODO: make a breaking condition
-    loop {
-        let (len, remote_addr) = match listener.recv_from(&mut buf) {
-            Ok(p) => p,
-            Err(e) => {
-                error!("Error while receiving multicast packet: {}", e);
-                continue;
-            }
-        };
-
-        let buddy_ip: IpAddr = remote_addr.ip();
+pub struct Multicast {
+    src_ip: IpAddr,
+    src_ifindex: u32,
+    group: SocketAddr,
+}
 
-        if buddy_ip == src_ip {
-            debug!("Sup from {}!", src_ip);
-            continue;
+impl Multicast {
+    pub fn new(
+        src_ip: IpAddr,
+        src_ifindex: u32,
+        multicast_ip: IpAddr,
+        multicast_port: u16,
+    ) -> Multicast {
+        Multicast {
+            src_ip,
+            src_ifindex,
+            group: SocketAddr::new(multicast_ip, multicast_port),
         }
+    }
+}
 
-        // trace!("MMM {:?}", &buf[..len]);
+impl Discovery for Multicast {
+    fn mechanism(&self) -> Mechanism {
+        Mechanism::Multicast
+    }
 
-        if len == SUP_LEN && buf[0..SUP_LEN] == SUP {
-            // Greetings to the newcommer.
-            if let Err(e) = listener.send_to(&SUP_REPLY, remote_addr) {
-                error!("Greeting the {} buddy failed: {}", buddy_ip, e);
-            }
-        }
+    fn start(&mut self) -> Result<UdpSocket, String> {
+        join_multicast_group(
+            &self.src_ip,
+            self.src_ifindex,
+            &self.group.ip(),
+            self.group.port(),
+        )
+        .map_err(|e| format!("Can't join multicast group on {}: {}", self.src_ip, e))
+    }
+
+    fn greet(
+        &mut self,
+        socket: &UdpSocket,
+        settings: &Settings,
+        _now: Instant,
+    ) -> Result<(), String> {
+        sup::send_hello(socket, self.group, settings)
+    }
 
-        match buddy_ip {
-            IpAddr::V4(remote_ipv4_addr) => {
-                e!(btx.send(Vpacket::M((direction_id, remote_ipv4_addr))))
-            }
-            IpAddr::V6(remote_ipv6_addr) => {
-                error!("Received packet from IPv6 address {}", remote_ipv6_addr)
-            }
+    fn stop(&mut self, socket: &UdpSocket) {
+        let left = match (self.src_ip, self.group.ip()) {
+            (IpAddr::V4(src), IpAddr::V4(m)) => socket.leave_multicast_v4(&m, &src),
+            (IpAddr::V6(_), IpAddr::V6(m)) => socket.leave_multicast_v6(&m, self.src_ifindex),
+            _ => Ok(()),
         };
+        if let Err(e) = left {
+            debug!("Can't leave multicast group {}: {}", self.group.ip(), e);
+        }
     }
-    // TODO: leave multicast group
 }
@@ -1,25 +1,21 @@
 // SPDX-FileCopyrightText: 2026 Vladyslav Tsilytskyi
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use std::{
-    net::{IpAddr, Ipv4Addr},
-    ops::BitAnd,
-};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
 
 use pcap::{Address, ConnectionStatus, Device};
 
+use crate::adapter_rules::{classify, load_rules, AdapterClass, Rule};
+use crate::link_layer::{can_inject, Link};
+use crate::liveness::Peers;
+use crate::subnet::{IpNet, Ipv4Net, Ipv6Net};
 use crate::{cli_parser, critical, debug, e, error, warn, Direction};
 
-// Not exhaustive, of course.
-const VIRT_NAMES: [&str; 1] = ["Virtual"];
-const VPN_NAMES: [&str; 2] = ["WireGuard", "OpenVPN"];
-const HW_NAMES: [&str; 16] = [
-    // Most popular first
-    "Broadcom", "Intel(R)", "MediaTek", "Qualcomm", "Realtek",
-    // And the rest in alphabet order
-    "AC1200", "ASIX", "Atheros", "Chelsio", "D-Link", "Dell", "JMicron", "Marvell", "Mellanox",
-    "QLogic", "Ralink",
-];
+/// Used when VPN adapter reports /32 netmask
+const DEFAULT_VPN_PREFIX: u8 = 24;
+/// Used when IPv6 VPN adapter reports no netmask
+const DEFAULT_VPN6_PREFIX: u8 = 64;
 
 /// Devices that are parsed by internal heuristic, may be overridden by user
 struct PromisingDevices {
@@ -44,13 +40,18 @@ pub fn get_promising_devices() -> Result<Vec<Device>, String> {
             d.flags.is_up()
                 && !d.flags.is_loopback()
                 && !d.addresses.is_empty()
-                && d.flags.connection_status == ConnectionStatus::Connected
+                && matches!(
+                    d.flags.connection_status,
+                    // Linux tunnels don't have a notion of connection
+                    ConnectionStatus::Connected | ConnectionStatus::NotApplicable
+                )
         })
         .collect();
     Ok(filtered)
 }
 
-pub fn print_devices(devs: &[Device]) {
+/// Print adapters and explain which rule classified each of them
+pub fn print_devices(devs: &[Device], rules: &[Rule]) {
     if crate::logger::is_monochrome() {
         println!(
             "Network adapter name                                IP address       Description"
@@ -59,7 +60,11 @@ pub fn print_devices(devs: &[Device]) {
         println!("\x1b[32mNetwork adapter name                                IP address       Description\x1b[0m");
     }
     for dev in devs {
-        let ip_opt: &Option<&Address> = &dev.addresses.iter().find(|a| a.addr.is_ipv4());
+        let ip_opt: &Option<&Address> = &dev
+            .addresses
+            .iter()
+            .find(|a| a.addr.is_ipv4())
+            .or(dev.addresses.first());
         if let Some(ip) = ip_opt {
             let row = format!(
                 "{0}  {1:W$}  {2}",
@@ -69,6 +74,58 @@ pub fn print_devices(devs: &[Device]) {
                 W = 15
             );
             println!("{}", row);
+            match classify(rules, dev) {
+                Some(r) => println!("    {} by rule \"{}\" at {}", r.class, r.text, r.origin),
+                None => println!("    unknown, no rule matched"),
+            }
+        }
+    }
+}
+
+/// Find device by exact name, IP address or part of description
+fn find_device<'a>(devs: &'a [Device], query: &str) -> Result<&'a Device, String> {
+    if let Some(d) = devs.iter().find(|d| d.name == query) {
+        return Ok(d);
+    }
+
+    if let Ok(ip) = query.parse::<IpAddr>() {
+        if let Some(d) = devs
+            .iter()
+            .find(|d| d.addresses.iter().any(|a| a.addr == ip))
+        {
+            return Ok(d);
+        }
+    }
+
+    let lowercase_query: String = query.to_lowercase();
+    let by_desc: Vec<&Device> = devs
+        .iter()
+        .filter(|d| {
+            d.desc
+                .clone()
+                .unwrap_or_default()
+                .to_lowercase()
+                .contains(&lowercase_query)
+        })
+        .collect();
+    match by_desc.len() {
+        1 => Ok(by_desc[0]),
+        0 => {
+            error!(
+                "Can't find {} network adapter. Did you mean one of those?",
+                query
+            );
+            for d in devs {
+                error!("\t{}  {}", d.name, d.desc.clone().unwrap_or_default());
+            }
+            Err(format!("Can't find {} network adapter.", query))
+        }
+        _ => {
+            error!("Several network adapters match {}:", query);
+            for d in by_desc {
+                error!("\t{}  {}", d.name, d.desc.clone().unwrap_or_default());
+            }
+            Err(format!("Ambiguous network adapter {}.", query))
         }
     }
 }
@@ -76,9 +133,13 @@ pub fn print_devices(devs: &[Device]) {
 /// Verify CLI arguments. Fill gaps.
 pub fn get_devices(a: &cli_parser::Arguments) -> Result<ParsedDevices, String> {
     let devs: Vec<Device> = get_promising_devices()?;
-    let split_devices: PromisingDevices = split_to_src_and_dst(devs.clone());
+    let rules: Vec<Rule> = load_rules(&a.rules)?;
+    let split_devices: PromisingDevices = split_to_src_and_dst(devs.clone(), &rules);
     show_virt_dev_warning(&split_devices.virt);
 
+    // User may select any adapter, even the one which our filter considers useless
+    let all_devs: Vec<Device> = e!(pcap::Device::list());
+
     let src: Device = match &a.srcdev {
         // No src device specified in CLI, let's do our best to find some in our list
         None => split_devices.src.ok_or(
@@ -86,12 +147,12 @@ pub fn get_devices(a: &cli_parser::Arguments) -> Result<ParsedDevices, String> {
         )?,
 
         // User specified an adapter via CLI, let's find it in our list
-        Some(name) => devs
-            .iter()
-            .find(|d| d.name == *name)
-            .ok_or(format!("Can't find {} network adapter.", name))?
-            .clone(),
+        Some(name) => find_device(&all_devs, name)?.clone(),
     };
+    if !usable_addresses(&src).iter().any(|(ip, _)| ip.is_ipv4()) {
+        critical!("{} network adapter has no IPv4 address.", src.name);
+        return Err(format!("No IPv4 address on {} device.", src.name));
+    }
 
     let dst: Vec<Device> = if a.dstdev.is_empty() {
         if split_devices.dst.is_empty() {
@@ -102,82 +163,239 @@ pub fn get_devices(a: &cli_parser::Arguments) -> Result<ParsedDevices, String> {
             split_devices.dst
         }
     } else {
-        let result: Vec<Device> = devs
-            .iter()
-            .filter_map(|d| {
-                for v in &a.dstdev {
-                    if d.name == *v {
-                        return Some(d.clone());
+        let mut result: Vec<Device> = Vec::with_capacity(a.dstdev.len());
+        for v in &a.dstdev {
+            let d: &Device = find_device(&all_devs, v)?;
+            if result.iter().any(|r| r.name == d.name) {
+                warn!("{} network adapter is specified twice.", d.name);
+                continue;
+            }
+            result.push(d.clone());
+        }
+        result
+    };
+    Ok(ParsedDevices { src, dst })
+}
+
+/// Which addresses of VPN adapter are used
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddressPreference {
+    /// First usable address
+    First,
+    /// Every usable address creates a separate direction
+    All,
+    /// Usable addresses from given networks, in order of networks
+    Networks(Vec<IpNet>),
+}
+
+/// Order of usable addresses: IPv4 first, then IPv6, link-local IPv4 are the last resort
+fn address_rank(ip: &IpAddr) -> u8 {
+    match ip {
+        IpAddr::V4(ip4) if ip4.is_link_local() => 2,
+        IpAddr::V4(_) => 0,
+        IpAddr::V6(_) => 1,
+    }
+}
+
+/// Addresses of the device, which can carry party traffic.
+/// IPv6 link-local addresses can't be routed through VPN, so they are skipped.
+fn usable_addresses(dev: &Device) -> Vec<(IpAddr, &Address)> {
+    let mut result: Vec<(IpAddr, &Address)> = dev
+        .addresses
+        .iter()
+        .filter(|a| match a.addr {
+            IpAddr::V4(ip4) => !ip4.is_unspecified() && !ip4.is_loopback(),
+            IpAddr::V6(ip6) => {
+                !ip6.is_unspecified()
+                    && !ip6.is_loopback()
+                    && !ip6.is_multicast()
+                    && (ip6.segments()[0] & 0xffc0) != 0xfe80
+            }
+        })
+        .map(|a| (a.addr, a))
+        .collect();
+    // Stable sort keeps adapter order otherwise
+    result.sort_by_key(|(ip, _)| address_rank(ip));
+    result
+}
+
+/// Pick addresses of VPN adapter according to user preference
+fn select_addresses<'a>(
+    dev: &'a Device,
+    preference: &AddressPreference,
+) -> Vec<(IpAddr, &'a Address)> {
+    let usable: Vec<(IpAddr, &Address)> = usable_addresses(dev);
+    match preference {
+        AddressPreference::First => usable.into_iter().take(1).collect(),
+        AddressPreference::All => usable,
+        AddressPreference::Networks(nets) => {
+            let mut result: Vec<(IpAddr, &Address)> = Vec::new();
+            for n in nets {
+                for u in &usable {
+                    if n.contains(&u.0) && !result.iter().any(|r| r.0 == u.0) {
+                        result.push(*u);
                     }
                 }
-                None
-            })
-            .collect();
-        if result.len() != a.dstdev.len() {
-            error!("Can't find all provided VPN devices.");
-            if !result.is_empty() {
-                error!("Devices, which are found:");
-                for r in result {
-                    error!("\t{}", r.name);
-                }
             }
-            return Err("VPN devices not found.".to_string());
-        } else {
             result
         }
+    }
+}
+
+/// Subnet of VPN address. Netmask of the adapter wins, unless it's /32 or /128,
+/// then user override, then point-to-point peer.
+/// Some adapters (Wireguard on Windows) report /32, so /24 is assumed for them.
+/// IPv6 addresses without netmask are assumed to be /64.
+fn vpn_subnet(address: &Address, ip: IpAddr, overrides: &[IpNet]) -> IpNet {
+    let reported: Option<IpNet> = match (ip, address.netmask) {
+        (IpAddr::V4(ip4), Some(IpAddr::V4(netmask))) => match Ipv4Net::from_netmask(ip4, netmask) {
+            Ok(n) if n.prefix < 32 => Some(IpNet::V4(n)),
+            Ok(_) => None,
+            Err(e) => {
+                warn!("{}", e);
+                None
+            }
+        },
+        (IpAddr::V6(ip6), Some(IpAddr::V6(netmask))) => match Ipv6Net::from_netmask(ip6, netmask) {
+            Ok(n) if n.prefix < 128 => Some(IpNet::V6(n)),
+            Ok(_) => None,
+            Err(e) => {
+                warn!("{}", e);
+                None
+            }
+        },
+        _ => None,
+    };
+    if let Some(n) = reported {
+        return n;
+    }
+
+    if let Some(o) = overrides.iter().find(|o| o.contains(&ip)) {
+        return match ip {
+            IpAddr::V4(addr) => IpNet::V4(Ipv4Net {
+                addr,
+                prefix: o.prefix(),
+            }),
+            IpAddr::V6(addr) => IpNet::V6(Ipv6Net {
+                addr,
+                prefix: o.prefix(),
+            }),
+        };
+    }
+
+    let ip4: Ipv4Addr = match ip {
+        IpAddr::V4(ip4) => ip4,
+        IpAddr::V6(ip6) => {
+            let fallback: Ipv6Net = Ipv6Net {
+                addr: ip6,
+                prefix: DEFAULT_VPN6_PREFIX,
+            };
+            debug!(
+                "VPN address {} has no usable netmask, assuming {}. Use --netmask to override.",
+                ip6, fallback
+            );
+            return IpNet::V6(fallback);
+        }
     };
-    Ok(ParsedDevices { src, dst })
+
+    let fallback: Ipv4Net = Ipv4Net {
+        addr: ip4,
+        prefix: DEFAULT_VPN_PREFIX,
+    };
+
+    if let Some(IpAddr::V4(peer)) = address.dst_addr {
+        let p2p: Ipv4Net = Ipv4Net::covering(ip4, peer);
+        if p2p.prefix < fallback.prefix {
+            return IpNet::V4(p2p);
+        }
+    }
+
+    debug!(
+        "VPN address {} has no usable netmask, assuming {}. Use --netmask to override.",
+        ip4, fallback
+    );
+    IpNet::V4(fallback)
+}
+
+/// Interface index is required to join IPv6 multicast group on the right adapter.
+/// Zero lets OS decide, which is the best we can do outside of Linux.
+fn interface_index(dev: &Device) -> u32 {
+    std::fs::read_to_string(format!("/sys/class/net/{}/ifindex", dev.name))
+        .ok()
+        .and_then(|s| s.trim().parse::<u32>().ok())
+        .unwrap_or(0)
 }
 
 /// Open all destination devices
 pub fn open_dst_devices(
     devices: ParsedDevices,
-    buddyip_slice: &[Ipv4Addr],
+    buddyip_slice: &[IpAddr],
+    scan: &[IpNet],
+    netmask_overrides: &[IpNet],
+    preference: &AddressPreference,
 ) -> Result<Vec<Direction>, String> {
-    if let IpAddr::V4(ip4) = &devices.src.addresses[0].addr {
-        if buddyip_slice.contains(ip4) {
+    for a in &devices.src.addresses {
+        if buddyip_slice.contains(&a.addr) {
             critical!(
                 "You specified {} as buddy address but it is actually your address.",
-                ip4
+                a.addr
             );
-            return Err(format!("Wrong buddy address {}", ip4));
+            return Err(format!("Wrong buddy address {}", a.addr));
         }
     }
 
-    let mut buddyip: Vec<Ipv4Addr> = buddyip_slice.to_vec();
+    let mut buddyip: Vec<IpAddr> = buddyip_slice.to_vec();
+    let mut scan: Vec<IpNet> = scan.to_vec();
 
     // For weirdos with multiple active VPNs
     let num_of_vpns = devices.dst.len();
-    let mut vpn_ipv4_cap: Vec<Direction> = Vec::with_capacity(num_of_vpns);
+    let mut vpn_cap: Vec<Direction> = Vec::with_capacity(num_of_vpns);
     for vpn in &devices.dst {
-        let addresses = &vpn.addresses[0];
-        if let IpAddr::V4(ip4) = addresses.addr {
-            // TODO: hardcode here because my Wireguard provides 255.255.255.255
-            let vpnmask: Ipv4Addr = Ipv4Addr::new(255, 255, 255, 0);
-            let precalc: Ipv4Addr = ip4.bitand(vpnmask);
-            let buddy_in_this_direction: Vec<Ipv4Addr> = buddyip
+        let selected: Vec<(IpAddr, &Address)> = select_addresses(vpn, preference);
+        if selected.is_empty() {
+            critical!(
+                "{} device has no usable IP address. Its addresses are {:?}.",
+                &vpn.name,
+                vpn.addresses.iter().map(|a| a.addr).collect::<Vec<_>>()
+            );
+            return Err(format!("No usable IP address on {} device.", &vpn.name));
+        }
+        let vpnifindex: u32 = interface_index(vpn);
+        // Every address is a separate direction
+        for (ip, addresses) in selected {
+            let vpnnet: IpNet = vpn_subnet(addresses, ip, netmask_overrides);
+            debug!("{} device has IP {} in {} network.", &vpn.name, ip, vpnnet);
+            let buddy_in_this_direction: Vec<IpAddr> = buddyip
                 .iter()
-                .filter(|buddy| precalc == buddy.bitand(vpnmask))
+                .filter(|buddy| vpnnet.contains(buddy))
                 .cloned()
                 .collect();
 
             // Is there buddy IP on this VPN connection?
             if buddy_in_this_direction.is_empty() {
                 debug!(
-                    "There are no buddy IP addresses that belongs to {} device with IP {} and netmask {}.", &vpn.name, ip4, vpnmask
+                    "There are no buddy IP addresses that belongs to {} device with IP {} and netmask {}.", &vpn.name, ip, vpnnet.netmask()
                 );
                 debug!("Your buddy IP list is {:?}", &buddyip);
             }
 
             // Check for intersection between buddy and own IPs
-            if buddy_in_this_direction.contains(&ip4) {
+            if buddy_in_this_direction.contains(&ip) {
                 critical!(
                     "You specified {} as buddy address but it is actually your address.",
-                    ip4
+                    ip
                 );
-                return Err(format!("Wrong buddy address {}", ip4));
+                return Err(format!("Wrong buddy address {}", ip));
             }
 
+            // Scan targets are swept from the VPN connection they belong to
+            let scan_in_this_direction: Vec<IpNet> = scan
+                .iter()
+                .filter(|net| vpnnet.contains_net(net))
+                .cloned()
+                .collect();
+            scan.retain(|net| !scan_in_this_direction.contains(net));
+
             // Remove used addresses from general list
             buddyip = buddyip
                 .iter()
@@ -186,73 +404,95 @@ pub fn open_dst_devices(
                 .collect();
 
             let v = e!(e!(pcap::Capture::from_device((*vpn).clone())).open());
-            vpn_ipv4_cap.push(Direction {
-                vpnip: ip4,
-                // vpndevice: vpn.clone(),
+            let vpnlink: Link = Link::of(vpn, &v);
+            if !can_inject(vpnlink.linktype) {
+                critical!(
+                    "Can't send packets to {} device with link type {:?}.",
+                    &vpn.name,
+                    vpnlink.linktype
+                );
+                return Err(format!("Unsupported link type of {} device.", &vpn.name));
+            }
+            debug!("{} device link type: {:?}", &vpn.name, vpnlink.linktype);
+            vpn_cap.push(Direction {
+                vpnip: ip,
+                vpnnet,
+                vpnifindex,
+                vpndevice: vpn.clone(),
                 vpncap: v,
+                vpnlink,
+                peers: Peers::new(buddy_in_this_direction.iter().cloned().collect()),
                 buddyip: buddy_in_this_direction.into_iter().collect(),
+                scan: scan_in_this_direction,
+                tunnels: HashMap::new(),
+                reaches: HashMap::new(),
             });
-        } else {
-            critical!("Error: IPv6 VPN address is not supported here.");
-            return Err("IPv6 VPN address is not supported here.".to_string());
         }
     }
     if !buddyip.is_empty() {
         critical!("Those IP addresses {:?} does not belong to any known VPN connection. Either correct IP address or specify a VPN connection via CLI.", buddyip);
         return Err("Redundant buddy IP.".to_string());
     }
-    Ok(vpn_ipv4_cap)
+    if !scan.is_empty() {
+        critical!("Those networks {:?} does not belong to any known VPN connection. Either correct the network or specify a VPN connection via CLI.", scan);
+        return Err("Redundant scan network.".to_string());
+    }
+    Ok(vpn_cap)
 }
 
 fn show_virt_dev_warning(virt: &[Device]) {
     if !virt.is_empty() {
         warn!("There are active virtual network adapters in your system.");
         warn!("To prevent troubles either disable virtual adapters or specify the correct HW adapter via command line.");
-        warn!("Here are PowerShell commands (run as Administrator):");
-        for vd in virt {
-            warn!(
-                "\tDisable-NetAdapter -InterfaceDescription  \"{}\"",
-                vd.desc.clone().unwrap()
-            );
-        }
-        warn!("Feel free to enable them back using following PowerShell commands:");
-        for vd in virt {
-            warn!(
-                "\tEnable-NetAdapter -InterfaceDescription  \"{}\"",
-                vd.desc.clone().unwrap()
-            );
-        }
-    }
-}
-
-/// Check whether any of the array elements appear in device description
-fn contains<E>(d: &&Device, patterns: E) -> bool
-where
-    E: IntoIterator<Item = &'static str>,
-{
-    for e in patterns {
-        if d.desc.clone().unwrap_or_default().contains(e) {
-            return true;
+        if cfg!(windows) {
+            warn!("Here are PowerShell commands (run as Administrator):");
+            for vd in virt {
+                warn!(
+                    "\tDisable-NetAdapter -InterfaceDescription  \"{}\"",
+                    vd.desc.clone().unwrap_or_default()
+                );
+            }
+            warn!("Feel free to enable them back using following PowerShell commands:");
+            for vd in virt {
+                warn!(
+                    "\tEnable-NetAdapter -InterfaceDescription  \"{}\"",
+                    vd.desc.clone().unwrap_or_default()
+                );
+            }
+        } else {
+            warn!("Here are shell commands (run as root):");
+            for vd in virt {
+                warn!("\tip link set {} down", vd.name);
+            }
+            warn!("Feel free to enable them back using following shell commands:");
+            for vd in virt {
+                warn!("\tip link set {} up", vd.name);
+            }
         }
     }
-    false
 }
 
 /// Consume devices and decide which device provides broadcast packets and which needs them
-fn split_to_src_and_dst(full_list: Vec<Device>) -> PromisingDevices {
-    let dst: Vec<Device> = full_list
-        .iter()
-        .filter(|d| contains(d, VPN_NAMES))
-        .cloned()
-        .collect();
-    let virt: Vec<Device> = full_list
+fn split_to_src_and_dst(full_list: Vec<Device>, rules: &[Rule]) -> PromisingDevices {
+    let classes: Vec<Option<AdapterClass>> = full_list
         .iter()
-        .filter(|d| contains(d, VIRT_NAMES))
-        .cloned()
+        .map(|d| classify(rules, d).map(|r| r.class))
         .collect();
-    let src: Option<Device> = full_list.iter().find(|d| contains(d, HW_NAMES)).cloned();
+    let of_class = |class: Option<AdapterClass>| -> Vec<Device> {
+        full_list
+            .iter()
+            .zip(&classes)
+            .filter(|(_, c)| **c == class)
+            .map(|(d, _)| d.clone())
+            .collect()
+    };
+
+    let dst: Vec<Device> = of_class(Some(AdapterClass::Vpn));
+    let virt: Vec<Device> = of_class(Some(AdapterClass::Virtual));
+    let src: Option<Device> = of_class(Some(AdapterClass::Source)).into_iter().next();
     if src.is_none() {
-        let guess = full_list.iter().find(|d| !contains(d, VPN_NAMES)).cloned();
+        // Prefer unknown adapters, but virtual one is still better than nothing
+        let guess = of_class(None).into_iter().next().or(virt.first().cloned());
         return PromisingDevices {
             src: guess,
             dst,
@@ -262,3 +502,158 @@ fn split_to_src_and_dst(full_list: Vec<Device>) -> PromisingDevices {
 
     PromisingDevices { src, dst, virt }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_devices::{device, ptp_device};
+
+    #[test]
+    fn find_device_test() {
+        let devs: Vec<Device> = vec![
+            device(
+                "eth0",
+                Some("Intel(R) Ethernet Connection"),
+                [192, 168, 0, 11],
+            ),
+            device("wg0", Some("WireGuard Tunnel"), [10, 0, 0, 1]),
+            device("tun0", Some("OpenVPN Data Channel Offload"), [10, 8, 0, 2]),
+        ];
+
+        assert_eq!(find_device(&devs, "wg0").unwrap().name, "wg0");
+        assert_eq!(find_device(&devs, "10.8.0.2").unwrap().name, "tun0");
+        assert_eq!(find_device(&devs, "intel").unwrap().name, "eth0");
+        assert_eq!(find_device(&devs, "WireGuard").unwrap().name, "wg0");
+
+        // Several descriptions contain "n"
+        assert!(find_device(&devs, "n").is_err());
+        assert!(find_device(&devs, "eth1").is_err());
+        assert!(find_device(&devs, "10.0.0.2").is_err());
+    }
+
+    #[test]
+    fn vpn_subnet_test() {
+        let ip = IpAddr::from([100, 101, 1, 2]);
+        let mut a = Address {
+            addr: ip,
+            netmask: Some(IpAddr::from([255, 255, 255, 255])),
+            broadcast_addr: None,
+            dst_addr: None,
+        };
+        assert_eq!(vpn_subnet(&a, ip, &[]).to_string(), "100.101.1.0/24");
+
+        let overrides: Vec<IpNet> = vec!["100.64.0.0/10".parse().unwrap()];
+        assert_eq!(vpn_subnet(&a, ip, &overrides).to_string(), "100.64.0.0/10");
+
+        a.netmask = Some(IpAddr::from([255, 255, 0, 0]));
+        assert_eq!(vpn_subnet(&a, ip, &[]).to_string(), "100.101.0.0/16");
+        // Override is only for adapters, which don't report their network
+        assert_eq!(vpn_subnet(&a, ip, &overrides).to_string(), "100.101.0.0/16");
+
+        a.netmask = None;
+        a.dst_addr = Some(IpAddr::from([100, 101, 0, 1]));
+        assert_eq!(vpn_subnet(&a, ip, &[]).to_string(), "100.101.0.0/23");
+        a.dst_addr = Some(IpAddr::from([100, 101, 1, 1]));
+        assert_eq!(vpn_subnet(&a, ip, &[]).to_string(), "100.101.1.0/24");
+
+        let ip6: IpAddr = "fd7a:115c:a1e0::5".parse().unwrap();
+        let a6 = Address {
+            addr: ip6,
+            netmask: None,
+            broadcast_addr: None,
+            dst_addr: None,
+        };
+        assert_eq!(vpn_subnet(&a6, ip6, &[]).to_string(), "fd7a:115c:a1e0::/64");
+        let overrides: Vec<IpNet> = vec![
+            "100.64.0.0/10".parse().unwrap(),
+            "fd7a:115c:a1e0::/48".parse().unwrap(),
+        ];
+        assert_eq!(
+            vpn_subnet(&a6, ip6, &overrides).to_string(),
+            "fd7a:115c:a1e0::/48"
+        );
+    }
+
+    #[test]
+    fn select_addresses_test() {
+        let mut dev: Device = device("wg0", Some("WireGuard Tunnel"), [169, 254, 7, 1]);
+        for ip in [
+            IpAddr::from([0xfe80, 0, 0, 0, 0, 0, 0, 1]),
+            IpAddr::from([10, 0, 0, 1]),
+            IpAddr::from([0xfd00, 0, 0, 0, 0, 0, 0, 1]),
+            IpAddr::from([172, 16, 0, 1]),
+        ] {
+            dev.addresses.push(Address {
+                addr: ip,
+                netmask: None,
+                broadcast_addr: None,
+                dst_addr: None,
+            });
+        }
+        let ips = |p: &AddressPreference| -> Vec<IpAddr> {
+            select_addresses(&dev, p).into_iter().map(|a| a.0).collect()
+        };
+
+        assert_eq!(
+            ips(&AddressPreference::First),
+            [IpAddr::from([10, 0, 0, 1])]
+        );
+        assert_eq!(
+            ips(&AddressPreference::All),
+            [
+                IpAddr::from([10, 0, 0, 1]),
+                IpAddr::from([172, 16, 0, 1]),
+                IpAddr::from([0xfd00, 0, 0, 0, 0, 0, 0, 1]),
+                IpAddr::from([169, 254, 7, 1])
+            ]
+        );
+        let nets = vec![
+            "172.16.0.0/12".parse().unwrap(),
+            "10.0.0.0/8".parse().unwrap(),
+        ];
+        assert_eq!(
+            ips(&AddressPreference::Networks(nets)),
+            [IpAddr::from([172, 16, 0, 1]), IpAddr::from([10, 0, 0, 1])]
+        );
+        let nets = vec!["fd00::/8".parse().unwrap()];
+        assert_eq!(
+            ips(&AddressPreference::Networks(nets)),
+            [IpAddr::from([0xfd00, 0, 0, 0, 0, 0, 0, 1])]
+        );
+        let nets = vec!["192.168.0.0/16".parse().unwrap()];
+        assert!(ips(&AddressPreference::Networks(nets)).is_empty());
+
+        let link_local_only: Device = Device {
+            addresses: vec![dev.addresses[1].clone()],
+            ..dev.clone()
+        };
+        assert!(select_addresses(&link_local_only, &AddressPreference::All).is_empty());
+    }
+
+    #[test]
+    fn split_linux_devices() {
+        let devs: Vec<Device> = vec![
+            device("docker0", None, [172, 17, 0, 1]),
+            device("enp3s0", None, [192, 168, 0, 11]),
+            device("wg0", None, [10, 0, 0, 1]),
+            device("tailscale0", None, [100, 64, 0, 7]),
+            ptp_device("vpn-office", [10, 9, 0, 5], [10, 9, 0, 1]),
+            device("veth1a2b3c", None, [172, 18, 0, 1]),
+        ];
+        let rules: Vec<Rule> = load_rules(&None).unwrap();
+        let split: PromisingDevices = split_to_src_and_dst(devs, &rules);
+        assert_eq!(split.src.unwrap().name, "enp3s0");
+        let dst: Vec<String> = split.dst.into_iter().map(|d| d.name).collect();
+        assert_eq!(dst, ["wg0", "tailscale0", "vpn-office"]);
+        let virt: Vec<String> = split.virt.into_iter().map(|d| d.name).collect();
+        assert_eq!(virt, ["docker0", "veth1a2b3c"]);
+
+        // No obvious HW adapter, virtual is the last resort
+        let devs: Vec<Device> = vec![
+            device("docker0", None, [172, 17, 0, 1]),
+            device("wg0", None, [10, 0, 0, 1]),
+            device("lan", None, [192, 168, 0, 11]),
+        ];
+        assert_eq!(split_to_src_and_dst(devs, &rules).src.unwrap().name, "lan");
+    }
+}
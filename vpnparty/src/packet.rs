@@ -0,0 +1,316 @@
+// SPDX-FileCopyrightText: 2026 Vladyslav Tsilytskyi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Validation of captured IPv4/UDP and IPv6/UDP packets
+
+use std::fmt;
+
+const IPV4_MIN_HEADER_LEN: usize = 20;
+const IPV6_HEADER_LEN: usize = 40;
+const UDP_HEADER_LEN: usize = 8;
+const UDP_PROTOCOL: u8 = 17;
+
+/// Reasons to reject a captured packet
+#[derive(Debug, PartialEq, Eq)]
+pub enum PacketError {
+    /// Capture or injection on this link layer is not supported
+    UnsupportedLinktype(i32),
+    /// Link layer carries something else than IP
+    NotIpEthertype(u16),
+    /// Buffer is shorter than the headers claim
+    Truncated { expected: usize, actual: usize },
+    /// IP version is not the expected one
+    BadIpVersion(u8),
+    /// Internet Header Length is less than 5 words
+    BadIhl(u8),
+    /// IP total length is less than header length
+    BadTotalLength(usize),
+    /// Only first fragment of a datagram was captured or packet is fragmented
+    Fragmented,
+    /// Not a UDP packet, IPv6 extension headers are not supported either
+    NotUdp(u8),
+    /// UDP length doesn't fit into IP packet
+    BadUdpLength(usize),
+}
+
+impl fmt::Display for PacketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PacketError::UnsupportedLinktype(l) => write!(f, "link type {} is not supported", l),
+            PacketError::NotIpEthertype(t) => write!(f, "ethertype 0x{:04x} is not IP", t),
+            PacketError::Truncated { expected, actual } => write!(
+                f,
+                "packet is truncated, expected {} bytes but got {}",
+                expected, actual
+            ),
+            PacketError::BadIpVersion(v) => write!(f, "IP version {} is not expected", v),
+            PacketError::BadIhl(ihl) => write!(f, "invalid IPv4 header length {}", ihl),
+            PacketError::BadTotalLength(l) => write!(f, "invalid IP packet length {}", l),
+            PacketError::Fragmented => write!(f, "fragmented packets are not supported"),
+            PacketError::NotUdp(p) => write!(f, "IP protocol {} is not UDP", p),
+            PacketError::BadUdpLength(l) => write!(f, "invalid UDP length {}", l),
+        }
+    }
+}
+
+/// Offsets of the valid IPv4 packet with UDP datagram inside
+#[derive(Debug, PartialEq, Eq)]
+pub struct Udp4Layout {
+    /// IPv4 header length in bytes, also the offset of UDP header
+    pub ihl: usize,
+    /// IPv4 total length, everything after it is link layer padding
+    pub total_len: usize,
+    /// UDP header and payload length
+    pub udp_len: usize,
+}
+
+/// Check that buffer starts with IPv4 header followed by complete UDP datagram
+pub fn parse_udp4(buf: &[u8]) -> Result<Udp4Layout, PacketError> {
+    if buf.len() < IPV4_MIN_HEADER_LEN {
+        return Err(PacketError::Truncated {
+            expected: IPV4_MIN_HEADER_LEN,
+            actual: buf.len(),
+        });
+    }
+
+    let version: u8 = buf[0] >> 4;
+    if version != 4 {
+        return Err(PacketError::BadIpVersion(version));
+    }
+
+    let ihl_words: u8 = buf[0] & 0x0F;
+    let ihl: usize = ihl_words as usize * 4;
+    if ihl < IPV4_MIN_HEADER_LEN {
+        return Err(PacketError::BadIhl(ihl_words));
+    }
+
+    let total_len: usize = ((buf[2] as usize) << 8) + (buf[3] as usize);
+    if total_len < ihl {
+        return Err(PacketError::BadTotalLength(total_len));
+    }
+    if total_len > buf.len() {
+        return Err(PacketError::Truncated {
+            expected: total_len,
+            actual: buf.len(),
+        });
+    }
+
+    // More fragments flag or non-zero fragment offset
+    if buf[6] & 0x3F != 0 || buf[7] != 0 {
+        return Err(PacketError::Fragmented);
+    }
+
+    if buf[9] != UDP_PROTOCOL {
+        return Err(PacketError::NotUdp(buf[9]));
+    }
+
+    if total_len < ihl + UDP_HEADER_LEN {
+        return Err(PacketError::Truncated {
+            expected: ihl + UDP_HEADER_LEN,
+            actual: total_len,
+        });
+    }
+
+    let udp_len: usize = ((buf[ihl + 4] as usize) << 8) + (buf[ihl + 5] as usize);
+    if udp_len < UDP_HEADER_LEN || ihl + udp_len > total_len {
+        return Err(PacketError::BadUdpLength(udp_len));
+    }
+
+    Ok(Udp4Layout {
+        ihl,
+        total_len,
+        udp_len,
+    })
+}
+
+/// Offsets of the valid IPv6 packet with UDP datagram right after fixed header
+#[derive(Debug, PartialEq, Eq)]
+pub struct Udp6Layout {
+    /// Fixed header and payload length, everything after it is link layer padding
+    pub total_len: usize,
+    /// UDP header and payload length
+    pub udp_len: usize,
+}
+
+/// Check that buffer starts with IPv6 header followed by complete UDP datagram
+pub fn parse_udp6(buf: &[u8]) -> Result<Udp6Layout, PacketError> {
+    if buf.len() < IPV6_HEADER_LEN {
+        return Err(PacketError::Truncated {
+            expected: IPV6_HEADER_LEN,
+            actual: buf.len(),
+        });
+    }
+
+    let version: u8 = buf[0] >> 4;
+    if version != 6 {
+        return Err(PacketError::BadIpVersion(version));
+    }
+
+    let payload_len: usize = ((buf[4] as usize) << 8) + (buf[5] as usize);
+    let total_len: usize = IPV6_HEADER_LEN + payload_len;
+    if total_len > buf.len() {
+        return Err(PacketError::Truncated {
+            expected: total_len,
+            actual: buf.len(),
+        });
+    }
+
+    if buf[6] != UDP_PROTOCOL {
+        return Err(PacketError::NotUdp(buf[6]));
+    }
+
+    if payload_len < UDP_HEADER_LEN {
+        return Err(PacketError::BadTotalLength(total_len));
+    }
+
+    let udp_len: usize = ((buf[44] as usize) << 8) + (buf[45] as usize);
+    if udp_len < UDP_HEADER_LEN || udp_len > payload_len {
+        return Err(PacketError::BadUdpLength(udp_len));
+    }
+
+    Ok(Udp6Layout { total_len, udp_len })
+}
+
+/// Check either IPv4 or IPv6 packet
+pub fn validate_udp(buf: &[u8]) -> Result<(), PacketError> {
+    match buf.first().map(|b| b >> 4) {
+        Some(4) => parse_udp4(buf).map(|_| ()),
+        Some(6) => parse_udp6(buf).map(|_| ()),
+        Some(v) => Err(PacketError::BadIpVersion(v)),
+        None => Err(PacketError::Truncated {
+            expected: 1,
+            actual: 0,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rustfmt::skip]
+    const TL2_BROADCAST: [u8; 35] = [
+        0x45, 0x00, 0x00, 0x23, 0x12, 0x34, 0x00, 0x00, 0x80, 0x11,
+        0x67, 0xe4, 0xc0, 0xa8, 0x00, 0x0a, 0xff, 0xff, 0xff, 0xff,
+        0x11, 0xc5, 0x11, 0xc5, 0x00, 0x0f, 0xef, 0x9c, 0xab, 0x84,
+        0x54, 0x72, 0x2c, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn valid_packet() {
+        let layout = parse_udp4(&TL2_BROADCAST).unwrap();
+        assert_eq!(
+            layout,
+            Udp4Layout {
+                ihl: 20,
+                total_len: 35,
+                udp_len: 15
+            }
+        );
+
+        let mut padded: Vec<u8> = TL2_BROADCAST.to_vec();
+        padded.extend_from_slice(&[0u8; 11]);
+        assert_eq!(parse_udp4(&padded).unwrap().total_len, 35);
+    }
+
+    #[test]
+    fn packet_with_options() {
+        // Same packet with a 4 byte option (IHL = 6)
+        let mut p: Vec<u8> = TL2_BROADCAST[..20].to_vec();
+        p[0] = 0x46;
+        p[3] = 0x27;
+        p.extend_from_slice(&[0x01, 0x01, 0x01, 0x00]);
+        p.extend_from_slice(&TL2_BROADCAST[20..]);
+        let layout = parse_udp4(&p).unwrap();
+        assert_eq!(layout.ihl, 24);
+        assert_eq!(layout.udp_len, 15);
+    }
+
+    #[test]
+    fn malformed_packets() {
+        assert_eq!(
+            parse_udp4(&TL2_BROADCAST[..10]),
+            Err(PacketError::Truncated {
+                expected: 20,
+                actual: 10
+            })
+        );
+        assert_eq!(
+            parse_udp4(&TL2_BROADCAST[..30]),
+            Err(PacketError::Truncated {
+                expected: 35,
+                actual: 30
+            })
+        );
+
+        let mut p: [u8; 35] = TL2_BROADCAST;
+        p[0] = 0x65;
+        assert_eq!(parse_udp4(&p), Err(PacketError::BadIpVersion(6)));
+
+        let mut p: [u8; 35] = TL2_BROADCAST;
+        p[0] = 0x44;
+        assert_eq!(parse_udp4(&p), Err(PacketError::BadIhl(4)));
+
+        let mut p: [u8; 35] = TL2_BROADCAST;
+        p[3] = 0x10;
+        assert_eq!(parse_udp4(&p), Err(PacketError::BadTotalLength(16)));
+
+        let mut p: [u8; 35] = TL2_BROADCAST;
+        p[6] = 0x20;
+        assert_eq!(parse_udp4(&p), Err(PacketError::Fragmented));
+
+        let mut p: [u8; 35] = TL2_BROADCAST;
+        p[9] = 0x06;
+        assert_eq!(parse_udp4(&p), Err(PacketError::NotUdp(6)));
+
+        let mut p: [u8; 35] = TL2_BROADCAST;
+        p[25] = 0x10;
+        assert_eq!(parse_udp4(&p), Err(PacketError::BadUdpLength(16)));
+        p[25] = 0x07;
+        assert_eq!(parse_udp4(&p), Err(PacketError::BadUdpLength(7)));
+    }
+
+    #[rustfmt::skip]
+    const IPV6_PACKET: [u8; 51] = [
+        0x60, 0x00, 0x00, 0x00, 0x00, 0x0b, 0x11, 0x01,
+        0xfe, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+        0xff, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+        0x11, 0xc5, 0x11, 0xc5, 0x00, 0x0b, 0x00, 0x00,
+        0xab, 0x84, 0x54,
+    ];
+
+    #[test]
+    fn ipv6_packet() {
+        assert_eq!(
+            parse_udp6(&IPV6_PACKET),
+            Ok(Udp6Layout {
+                total_len: 51,
+                udp_len: 11
+            })
+        );
+        assert_eq!(validate_udp(&IPV6_PACKET), Ok(()));
+        assert_eq!(validate_udp(&TL2_BROADCAST), Ok(()));
+        assert_eq!(
+            parse_udp6(&TL2_BROADCAST),
+            Err(PacketError::Truncated {
+                expected: 40,
+                actual: 35
+            })
+        );
+        assert_eq!(parse_udp4(&IPV6_PACKET), Err(PacketError::BadIpVersion(6)));
+
+        let mut p: [u8; 51] = IPV6_PACKET;
+        p[6] = 0x00; // Hop-by-hop options
+        assert_eq!(parse_udp6(&p), Err(PacketError::NotUdp(0)));
+
+        let mut p: [u8; 51] = IPV6_PACKET;
+        p[45] = 0x0c;
+        assert_eq!(parse_udp6(&p), Err(PacketError::BadUdpLength(12)));
+
+        assert!(parse_udp6(&IPV6_PACKET[..50]).is_err());
+        assert!(validate_udp(&[]).is_err());
+        assert_eq!(validate_udp(&[0x70]), Err(PacketError::BadIpVersion(7)));
+    }
+}
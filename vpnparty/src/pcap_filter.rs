@@ -2,7 +2,7 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use pcap::Address;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr};
 
 /// Accepts zero or more ports
 pub fn port_filter(ports: &[u16]) -> String {
@@ -24,13 +24,15 @@ pub fn port_filter(ports: &[u16]) -> String {
     filter
 }
 
-/// At least one address must be
-pub fn host_filter(addr: &[Address]) -> String {
+/// At least one IPv4 address must be
+pub fn host_filter(addr: &[Address]) -> Result<String, String> {
     let addresses = addr
         .iter()
         .filter(|v| matches!(v.addr, IpAddr::V4(_)))
         .collect::<Vec<_>>();
-    assert_ne!(addresses.len(), 0);
+    if addresses.is_empty() {
+        return Err("Device has no IPv4 address to build capture filter.".to_string());
+    }
     let host_filter: String = if addresses.len() == 1 {
         format!("(src host {})", addresses[0].addr)
     } else {
@@ -43,7 +45,32 @@ pub fn host_filter(addr: &[Address]) -> String {
                 .join(" or ")
         )
     };
-    host_filter
+    Ok(host_filter)
+}
+
+/// Same as host_filter, but for IPv6 addresses. Link-local addresses are included.
+pub fn host6_filter(addr: &[Address]) -> Option<String> {
+    let addresses = addr
+        .iter()
+        .filter(|v| matches!(v.addr, IpAddr::V6(_)))
+        .map(|v| format!("(src host {})", v.addr))
+        .collect::<Vec<_>>();
+    match addresses.len() {
+        0 => None,
+        1 => Some(addresses[0].clone()),
+        _ => Some(format!("({})", addresses.join(" or "))),
+    }
+}
+
+/// Packets forwarded to the address by buddies carry the mark in IPv4 identification,
+/// low 4 bits of it are the hop count
+pub fn forwarded_filter(vpnip: Ipv4Addr, mark: u16, ports: &[u16]) -> String {
+    format!(
+        "ip and udp and (dst host {}) and ((ip[4:2] & 0xfff0) = {}){}",
+        vpnip,
+        mark,
+        port_filter(ports)
+    )
 }
 
 #[cfg(test)]
@@ -66,13 +93,19 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
+    fn forwarded_filter_test() {
+        assert_eq!(
+            forwarded_filter(Ipv4Addr::new(10, 0, 0, 1), 30320, &[6112]),
+            "ip and udp and (dst host 10.0.0.1) and ((ip[4:2] & 0xfff0) = 30320) and (dst port 6112)"
+        );
+    }
+
+    #[test]
     fn empty_host_filter_test() {
-        host_filter(&[]);
+        assert!(host_filter(&[]).is_err());
     }
 
     #[test]
-    #[should_panic]
     fn ipv6_host_filter_test() {
         let addr1: pcap::Address = pcap::Address {
             addr: IpAddr::from([
@@ -82,7 +115,7 @@ mod tests {
             broadcast_addr: None,
             dst_addr: None,
         };
-        host_filter(&[addr1.clone(), addr1.clone(), addr1.clone()]);
+        assert!(host_filter(&[addr1.clone(), addr1.clone(), addr1.clone()]).is_err());
     }
 
     #[test]
@@ -93,7 +126,7 @@ mod tests {
             broadcast_addr: None,
             dst_addr: None,
         };
-        let one_host: String = host_filter(std::slice::from_ref(&addr1));
+        let one_host: String = host_filter(std::slice::from_ref(&addr1)).unwrap();
         assert_eq!(one_host, "(src host 192.168.0.11)");
 
         let addr2: pcap::Address = pcap::Address {
@@ -104,7 +137,7 @@ mod tests {
             broadcast_addr: None,
             dst_addr: None,
         };
-        let one_ipv4_host: String = host_filter(&[addr2.clone(), addr1.clone()]);
+        let one_ipv4_host: String = host_filter(&[addr2.clone(), addr1.clone()]).unwrap();
         assert_eq!(one_ipv4_host, "(src host 192.168.0.11)");
 
         let addr3: pcap::Address = pcap::Address {
@@ -119,10 +152,43 @@ mod tests {
             broadcast_addr: None,
             dst_addr: None,
         };
-        let three_ipv4_hosts: String = host_filter(&[addr1, addr2, addr3, addr4]);
+        let three_ipv4_hosts: String = host_filter(&[addr1, addr2, addr3, addr4]).unwrap();
         assert_eq!(
             three_ipv4_hosts,
             "((src host 192.168.0.11) or (src host 10.1.1.12) or (src host 172.16.2.13))"
         );
     }
+
+    #[test]
+    fn host6_filter_test() {
+        let addr4: Address = Address {
+            addr: IpAddr::from([192, 168, 0, 11]),
+            netmask: None,
+            broadcast_addr: None,
+            dst_addr: None,
+        };
+        assert_eq!(host6_filter(std::slice::from_ref(&addr4)), None);
+
+        let addr6: Address = Address {
+            addr: IpAddr::from([0xfe80, 0, 0, 0, 0, 0, 0, 0x11]),
+            netmask: None,
+            broadcast_addr: None,
+            dst_addr: None,
+        };
+        assert_eq!(
+            host6_filter(&[addr4.clone(), addr6.clone()]),
+            Some("(src host fe80::11)".to_string())
+        );
+
+        let ula: Address = Address {
+            addr: IpAddr::from([0xfd00, 0, 0, 0, 0, 0, 0, 0x11]),
+            netmask: None,
+            broadcast_addr: None,
+            dst_addr: None,
+        };
+        assert_eq!(
+            host6_filter(&[addr6, addr4, ula]),
+            Some("((src host fe80::11) or (src host fd00::11))".to_string())
+        );
+    }
 }
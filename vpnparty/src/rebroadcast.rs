@@ -0,0 +1,229 @@
+// SPDX-FileCopyrightText: 2026 Vladyslav Tsilytskyi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Forwarded broadcasts become genuine broadcasts again on the buddy's host or its LAN.
+//! Some games ignore announcements, which were not sent to a broadcast address.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::mpsc::Sender;
+
+use pcap::{Active, Capture, Device, Linktype};
+
+use crate::capture::capture_ip_packets;
+use crate::link_layer::{can_inject, Link};
+use crate::packet::{parse_udp4, parse_udp6, PacketError};
+use crate::pcap_filter::forwarded_filter;
+use crate::udp::{craft_udp6_packet, craft_udp_packet};
+use crate::{debug, e, Vpacket};
+
+/// IPv4 identification of forwarded packets, so buddy can tell them from regular unicast.
+/// Forwarded broadcasts are never fragmented, so identification isn't needed for reassembly.
+pub const FORWARD_MARK: u16 = 0x7670;
+
+/// Relays on the way are counted in the low bits of the mark
+pub const MAX_HOPS: u8 = 0x0f;
+
+/// mDNS group and port, where games of the LAN listen for announcements
+pub const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+pub const MDNS_LAN_PORT: u16 = 5353;
+
+/// Identification of packet, which was relayed given number of times
+pub fn mark(hops: u8) -> u16 {
+    FORWARD_MARK | std::cmp::min(hops, MAX_HOPS) as u16
+}
+
+/// Relays on the way of marked IPv4 packet
+pub fn hops(ip_packet: &[u8]) -> u8 {
+    ip_packet.get(5).map_or(0, |b| b & MAX_HOPS)
+}
+
+/// Packet, which buddy forwarded to us
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Forwarded {
+    pub direction_id: usize,
+    /// IP packet without link layer header
+    pub packet: Vec<u8>,
+    /// Packet came via UDP tunnel, so this host hasn't received it yet
+    pub tunneled: bool,
+    /// Relays on the way, zero if buddy captured the packet itself
+    pub hops: u8,
+}
+
+/// Capture packets, which buddies forwarded to us via the VPN device
+pub fn listen_forwarded(
+    vpndev: Device,
+    vpnip: Ipv4Addr,
+    direction_id: usize,
+    ftx: Sender<Vpacket>,
+    ports: &[u16],
+) -> Result<(), String> {
+    let filter: String = forwarded_filter(vpnip, FORWARD_MARK, ports);
+    debug!("Forwarded packets filter: {}", filter);
+
+    capture_ip_packets(vpndev, &filter, 577, "forwarded", |ip_packet| {
+        e!(ftx.send(Vpacket::F(Forwarded {
+            direction_id,
+            packet: ip_packet.to_vec(),
+            tunneled: false,
+            hops: hops(ip_packet),
+        })));
+        Ok(())
+    })
+}
+
+/// Packets injected into loopback device are delivered to the games on this host
+pub fn open_loopback() -> Result<(Capture<Active>, Link), String> {
+    let loopback: Device = e!(Device::list())
+        .into_iter()
+        .find(|d| d.flags.is_loopback())
+        .ok_or("There is no loopback device to re-broadcast packets. Install Npcap with loopback support.")?;
+    open_injection(loopback)
+}
+
+/// Open device to inject forwarded packets into
+pub fn open_injection(device: Device) -> Result<(Capture<Active>, Link), String> {
+    let cap: Capture<Active> = e!(e!(pcap::Capture::from_device(device.clone())).open());
+    let link: Link = Link::of(&device, &cap);
+    let linktype: Linktype = link.linktype;
+    if !can_inject(linktype) {
+        return Err(format!(
+            "Can't send packets to {} device with link type {:?}.",
+            device.name, linktype
+        ));
+    }
+    debug!(
+        "Forwarded packets go to {} device with link type {:?}.",
+        device.name, linktype
+    );
+    Ok((cap, link))
+}
+
+/// Buddy, which forwarded the packet
+pub fn source(ip_packet: &[u8]) -> Result<IpAddr, PacketError> {
+    if ip_packet.first().map(|b| b >> 4) == Some(6) {
+        parse_udp6(ip_packet)?;
+        let src: [u8; 16] = ip_packet[8..24].try_into().unwrap_or_default();
+        return Ok(IpAddr::from(src));
+    }
+    parse_udp4(ip_packet)?;
+    Ok(IpAddr::from([
+        ip_packet[12],
+        ip_packet[13],
+        ip_packet[14],
+        ip_packet[15],
+    ]))
+}
+
+/// Destination port of the forwarded IPv4 packet
+pub fn dst_port(ip_packet: &[u8]) -> Result<u16, PacketError> {
+    let layout = parse_udp4(ip_packet)?;
+    Ok(u16::from_be_bytes([
+        ip_packet[layout.ihl + 2],
+        ip_packet[layout.ihl + 3],
+    ]))
+}
+
+/// Restore broadcast destination of the forwarded IPv4 packet. Source address and ports stay,
+/// so the game answers the buddy via VPN.
+pub fn craft_broadcast(ip_packet: &[u8], dst: Ipv4Addr) -> Result<Vec<u8>, PacketError> {
+    let IpAddr::V4(src) = source(ip_packet)? else {
+        return Err(PacketError::BadIpVersion(6));
+    };
+    craft_udp_packet(ip_packet, &src.octets(), &dst.octets(), None, Some(0))
+}
+
+/// Broadcast of the forwarded packet on LAN. Mark stays, so the packet isn't captured
+/// and forwarded back to buddies.
+pub fn craft_lan_broadcast(ip_packet: &[u8], hops: u8) -> Result<Vec<u8>, PacketError> {
+    match source(ip_packet)? {
+        IpAddr::V4(src) => craft_udp_packet(
+            ip_packet,
+            &src.octets(),
+            &Ipv4Addr::BROADCAST.octets(),
+            None,
+            Some(mark(hops)),
+        ),
+        IpAddr::V6(src) => {
+            let all_nodes: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+            craft_udp6_packet(ip_packet, &src.octets(), &all_nodes.octets(), None)
+        }
+    }
+}
+
+/// mDNS of the buddy on LAN, it goes to the multicast group and port it was captured from.
+/// Mark stays, so the packet isn't captured and forwarded back to buddies.
+pub fn craft_lan_mdns(ip_packet: &[u8]) -> Result<Vec<u8>, PacketError> {
+    let IpAddr::V4(src) = source(ip_packet)? else {
+        return Err(PacketError::BadIpVersion(6));
+    };
+    craft_udp_packet(
+        ip_packet,
+        &src.octets(),
+        &MDNS_GROUP.octets(),
+        Some(MDNS_LAN_PORT),
+        Some(mark(0)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restore_broadcast() {
+        #[rustfmt::skip]
+        let announcement: [u8; 30] = [
+            0x45, 0x00, 0x00, 0x1e, 0x12, 0x34, 0x00, 0x00, 0x80, 0x11,
+            0x00, 0x00, 0xc0, 0xa8, 0x00, 0x0a, 0xff, 0xff, 0xff, 0xff,
+            0x17, 0xe0, 0x17, 0xe0, 0x00, 0x0a, 0x00, 0x00, 0xf7, 0x2f,
+        ];
+        let buddy: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+        let forwarded: Vec<u8> = craft_udp_packet(
+            &announcement,
+            &buddy.octets(),
+            &[10, 0, 0, 2],
+            None,
+            Some(mark(2)),
+        )
+        .unwrap();
+        assert_eq!(source(&forwarded), Ok(IpAddr::V4(buddy)));
+        assert_eq!(hops(&forwarded), 2);
+
+        let restored: Vec<u8> = craft_broadcast(&forwarded, Ipv4Addr::BROADCAST).unwrap();
+        assert_eq!(restored[4..6], [0, 0]);
+        assert_eq!(restored[12..20], [10, 0, 0, 1, 255, 255, 255, 255]);
+        assert_eq!(restored[20..], announcement[20..]);
+
+        let lan: Vec<u8> = craft_lan_broadcast(&forwarded, 2).unwrap();
+        assert_eq!(lan[4..6], mark(2).to_be_bytes());
+        assert_eq!(mark(100), FORWARD_MARK | 0x0f);
+        assert_eq!(lan[12..20], restored[12..20]);
+        assert!(source(&forwarded[..25]).is_err());
+    }
+
+    #[test]
+    fn mdns_to_lan() {
+        #[rustfmt::skip]
+        let query: [u8; 30] = [
+            0x45, 0x00, 0x00, 0x1e, 0x12, 0x34, 0x00, 0x00, 0x01, 0x11,
+            0x00, 0x00, 0xc0, 0xa8, 0x00, 0x0a, 0xe0, 0x00, 0x00, 0xfb,
+            0x14, 0xe9, 0x14, 0xe9, 0x00, 0x0a, 0x00, 0x00, 0xf7, 0x2f,
+        ];
+        let forwarded: Vec<u8> = craft_udp_packet(
+            &query,
+            &[10, 0, 0, 1],
+            &[10, 0, 0, 2],
+            Some(54927),
+            Some(mark(0)),
+        )
+        .unwrap();
+        assert_eq!(dst_port(&forwarded), Ok(54927));
+
+        let lan: Vec<u8> = craft_lan_mdns(&forwarded).unwrap();
+        assert_eq!(lan[4..6], mark(0).to_be_bytes());
+        assert_eq!(lan[12..20], [10, 0, 0, 1, 224, 0, 0, 251]);
+        assert_eq!(dst_port(&lan), Ok(MDNS_LAN_PORT));
+        assert_eq!(lan[28..], query[28..]);
+        assert!(dst_port(&forwarded[..25]).is_err());
+    }
+}
@@ -0,0 +1,109 @@
+// SPDX-FileCopyrightText: 2026 Vladyslav Tsilytskyi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Schedule of repeated peer discovery
+
+use std::io;
+use std::time::{Duration, Instant};
+
+/// Interval grows up to this many times of the base one while nobody new shows up
+const MAX_BACKOFF: u32 = 16;
+
+/// Repeats discovery with exponential backoff.
+/// Starts with base interval, doubles it after every round without new peers.
+#[derive(Debug, Clone)]
+pub struct Refresh {
+    base: Duration,
+    current: Duration,
+    next: Option<Instant>,
+}
+
+impl Refresh {
+    /// Zero interval disables refresh
+    pub fn new(base: Duration) -> Refresh {
+        Refresh {
+            base,
+            current: base,
+            next: if base.is_zero() {
+                None
+            } else {
+                Some(Instant::now() + base)
+            },
+        }
+    }
+
+    /// Time to repeat discovery
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.next.is_some_and(|n| now >= n)
+    }
+
+    /// Discovery was repeated, back off before the next round
+    pub fn done(&mut self, now: Instant) {
+        if self.next.is_none() {
+            return;
+        }
+        self.current = std::cmp::min(self.current * 2, self.base * MAX_BACKOFF);
+        self.next = Some(now + self.current);
+    }
+
+    /// New peer appeared, so party is changing. Return to base interval.
+    pub fn reset(&mut self, now: Instant) {
+        if self.next.is_none() || self.current == self.base {
+            return;
+        }
+        self.current = self.base;
+        self.next = Some(now + self.current);
+    }
+
+    /// How long to wait for incoming packets before checking the schedule.
+    /// None means wait forever.
+    pub fn wait(&self, now: Instant) -> Option<Duration> {
+        self.next.map(|n| {
+            // Zero read timeout is an error for sockets
+            std::cmp::max(n.saturating_duration_since(now), Duration::from_millis(1))
+        })
+    }
+}
+
+/// Socket read timeout is reported differently on Windows and Unix
+pub fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff() {
+        let base = Duration::from_secs(10);
+        let mut r = Refresh::new(base);
+        let start = r.next.unwrap() - base;
+        assert!(!r.is_due(start));
+        assert!(r.is_due(start + base));
+
+        r.done(start + base);
+        assert_eq!(r.current, Duration::from_secs(20));
+        for _ in 0..10 {
+            r.done(start);
+        }
+        assert_eq!(r.current, Duration::from_secs(160));
+
+        r.reset(start);
+        assert_eq!(r.current, base);
+        assert!(r.is_due(start + base));
+    }
+
+    #[test]
+    fn disabled() {
+        let now = Instant::now();
+        let mut r = Refresh::new(Duration::ZERO);
+        r.done(now);
+        r.reset(now);
+        assert!(!r.is_due(now + Duration::from_secs(3600)));
+        assert_eq!(r.wait(now), None);
+    }
+}
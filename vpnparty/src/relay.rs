@@ -0,0 +1,149 @@
+// SPDX-FileCopyrightText: 2026 Vladyslav Tsilytskyi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Relay of game broadcasts for buddies, which can't reach each other directly.
+//! VPNs like hub-and-spoke setups connect every buddy to a few others only.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
+
+use crate::packet::{parse_udp4, parse_udp6, PacketError};
+
+/// Relays on the way of the packet, unless given via --relay
+pub const DEFAULT_RELAY_HOPS: u8 = 2;
+
+/// Packet with the same content arriving within this time is a copy taken another way
+const DUPLICATE_WINDOW: Duration = Duration::from_millis(500);
+
+/// Packets remembered at once, the oldest ones are forgotten beyond this number
+const MAX_RECENT: usize = 1024;
+
+/// Recently relayed packets, so copies forwarded by the same buddy again are not relayed again.
+/// Copies coming around a loop arrive from another buddy, the hop limit stops them.
+#[derive(Debug, Default)]
+pub struct Recent {
+    seen: HashMap<u64, Instant>,
+}
+
+impl Recent {
+    /// Remember the packet of the direction, false if it was relayed recently
+    pub fn is_new(
+        &mut self,
+        direction_id: usize,
+        ip_packet: &[u8],
+        now: Instant,
+    ) -> Result<bool, PacketError> {
+        let print: u64 = fingerprint(direction_id, ip_packet)?;
+        if let Some(seen) = self.seen.get(&print) {
+            if now.saturating_duration_since(*seen) < DUPLICATE_WINDOW {
+                return Ok(false);
+            }
+        }
+        if self.seen.len() >= MAX_RECENT {
+            self.seen
+                .retain(|_, t| now.saturating_duration_since(*t) < DUPLICATE_WINDOW);
+        }
+        if self.seen.len() >= MAX_RECENT {
+            if let Some(oldest) = self.seen.iter().min_by_key(|(_, t)| **t).map(|(p, _)| *p) {
+                self.seen.remove(&oldest);
+            }
+        }
+        self.seen.insert(print, now);
+        Ok(true)
+    }
+}
+
+/// Hash of direction, source address, UDP ports, length and payload. Identical broadcasts
+/// of two buddies differ by source. Destination and checksum are left out.
+fn fingerprint(direction_id: usize, ip_packet: &[u8]) -> Result<u64, PacketError> {
+    let (src, offset, udp_len) = match ip_packet.first().map(|b| b >> 4) {
+        Some(6) => (8..24, 40, parse_udp6(ip_packet)?.udp_len),
+        _ => {
+            let layout = parse_udp4(ip_packet)?;
+            (12..16, layout.ihl, layout.udp_len)
+        }
+    };
+    let datagram: &[u8] = &ip_packet[offset..offset + udp_len];
+    let mut hasher = DefaultHasher::new();
+    direction_id.hash(&mut hasher);
+    ip_packet[src].hash(&mut hasher);
+    datagram[..6].hash(&mut hasher);
+    datagram[8..].hash(&mut hasher);
+    Ok(hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::udp::craft_udp_packet;
+
+    fn announcement(src: [u8; 4], payload: &[u8]) -> Vec<u8> {
+        let udp_len: usize = 8 + payload.len();
+        let total_len: usize = 20 + udp_len;
+        let mut p: Vec<u8> = vec![0x45, 0, 0, total_len as u8, 0, 0, 0, 0, 64, 17, 0, 0];
+        p.extend_from_slice(&src);
+        p.extend_from_slice(&[255, 255, 255, 255]);
+        p.extend_from_slice(&[0x17, 0xe0, 0x17, 0xe0, 0, udp_len as u8, 0, 0]);
+        p.extend_from_slice(payload);
+        p
+    }
+
+    #[test]
+    fn duplicates() {
+        let t0 = Instant::now();
+        let mut r = Recent::default();
+        let packet = announcement([10, 0, 0, 2], b"wc3 game");
+        assert_eq!(r.is_new(0, &packet, t0), Ok(true));
+        assert_eq!(r.is_new(0, &packet, t0), Ok(false));
+
+        // Destination, mark and checksum don't matter, it's still the same packet
+        let again = craft_udp_packet(
+            &packet,
+            &[10, 0, 0, 2],
+            &[10, 0, 0, 4],
+            None,
+            Some(crate::rebroadcast::mark(1)),
+        )
+        .unwrap();
+        assert_eq!(r.is_new(0, &again, t0), Ok(false));
+        assert_eq!(
+            r.is_new(0, &announcement([10, 0, 0, 2], b"wc3 gamf"), t0),
+            Ok(true)
+        );
+        assert_eq!(r.is_new(1, &packet, t0), Ok(true));
+
+        // Game repeats its announcement later
+        let later = t0 + DUPLICATE_WINDOW;
+        assert_eq!(r.is_new(0, &packet, later), Ok(true));
+        assert!(r.is_new(0, &packet[..10], later).is_err());
+    }
+
+    #[test]
+    fn same_payload_of_two_buddies() {
+        let t0 = Instant::now();
+        let mut r = Recent::default();
+        assert_eq!(
+            r.is_new(0, &announcement([10, 0, 0, 2], b"wc3 game"), t0),
+            Ok(true)
+        );
+        assert_eq!(
+            r.is_new(0, &announcement([10, 0, 0, 3], b"wc3 game"), t0),
+            Ok(true)
+        );
+    }
+
+    #[test]
+    fn many_packets() {
+        let t0 = Instant::now();
+        let mut r = Recent::default();
+        for i in 0..=MAX_RECENT as u32 {
+            assert_eq!(
+                r.is_new(0, &announcement([10, 0, 0, 2], &i.to_be_bytes()), t0),
+                Ok(true)
+            );
+        }
+        assert_eq!(r.seen.len(), MAX_RECENT);
+    }
+}
@@ -0,0 +1,210 @@
+// SPDX-FileCopyrightText: 2026 Vladyslav Tsilytskyi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Buddies reachable via several VPN connections. Packets go via a single preferred path.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Smoothing of measured round trip time, weight of the new sample is 1/RTT_SMOOTHING
+const RTT_SMOOTHING: u32 = 4;
+
+/// Path with the same priority must be this many times faster to replace preferred one
+const RTT_SWITCH_RATIO: f64 = 0.75;
+
+/// VPN connection and the buddy address on it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Path {
+    pub direction_id: usize,
+    pub ip: IpAddr,
+}
+
+/// All paths of a single buddy and the one packets are forwarded to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Routes {
+    pub id: u64,
+    pub paths: Vec<Path>,
+    pub preferred: Option<Path>,
+}
+
+#[derive(Debug, Default)]
+struct Buddy {
+    /// Smoothed round trip time of every path, if measured
+    paths: HashMap<Path, Option<Duration>>,
+    preferred: Option<Path>,
+}
+
+/// Buddies identified by the Sup sender ID, which is the same on every VPN connection
+#[derive(Debug, Default)]
+pub struct Roster {
+    buddies: HashMap<u64, Buddy>,
+    ids: HashMap<Path, u64>,
+    /// Lower is better, indexed by direction
+    priorities: Vec<usize>,
+}
+
+impl Roster {
+    pub fn new(priorities: Vec<usize>) -> Roster {
+        Roster {
+            priorities,
+            ..Default::default()
+        }
+    }
+
+    /// ID of the buddy on the path
+    pub fn id(&self, path: &Path) -> Option<u64> {
+        self.ids.get(path).copied()
+    }
+
+    /// Buddy answered via at least one path
+    pub fn is_known(&self, id: u64) -> bool {
+        self.buddies.contains_key(&id)
+    }
+
+    /// Buddy answered via the path. Routes of the previous buddy on this address follow, if any.
+    pub fn seen(&mut self, id: u64, path: Path) -> Vec<Routes> {
+        // Address is taken by another instance after restart
+        let previous: Option<Routes> = match self.ids.get(&path) {
+            Some(old) if *old != id => self.lost(path),
+            _ => None,
+        };
+        self.ids.insert(path, id);
+        let buddy: &mut Buddy = self.buddies.entry(id).or_default();
+        buddy.paths.entry(path).or_insert(None);
+        let mut routes: Vec<Routes> = vec![self.select(id)];
+        routes.extend(previous);
+        routes
+    }
+
+    /// Round trip time of the path was measured
+    pub fn rtt(&mut self, path: Path, rtt: Duration) -> Option<Routes> {
+        let id: u64 = self.id(&path)?;
+        let smoothed: &mut Option<Duration> = self.buddies.get_mut(&id)?.paths.get_mut(&path)?;
+        *smoothed = Some(match smoothed {
+            Some(old) => (*old * (RTT_SMOOTHING - 1) + rtt) / RTT_SMOOTHING,
+            None => rtt,
+        });
+        Some(self.select(id))
+    }
+
+    /// Path is gone. Lost path is listed in routes, so it can be cleaned up.
+    pub fn lost(&mut self, path: Path) -> Option<Routes> {
+        let id: u64 = self.ids.remove(&path)?;
+        let buddy: &mut Buddy = self.buddies.get_mut(&id)?;
+        buddy.paths.remove(&path);
+        if buddy.preferred == Some(path) {
+            buddy.preferred = None;
+        }
+        let mut routes: Routes = self.select(id);
+        if routes.paths.is_empty() {
+            self.buddies.remove(&id);
+        }
+        routes.paths.push(path);
+        Some(routes)
+    }
+
+    fn priority(&self, path: &Path) -> usize {
+        self.priorities
+            .get(path.direction_id)
+            .copied()
+            .unwrap_or(usize::MAX)
+    }
+
+    /// Highest priority path, the fastest one among equals. Preferred path is kept unless
+    /// another one is clearly better, so similar paths don't flap.
+    fn select(&mut self, id: u64) -> Routes {
+        let Some(buddy) = self.buddies.get(&id) else {
+            return Routes {
+                id,
+                paths: Vec::new(),
+                preferred: None,
+            };
+        };
+        let key = |p: &Path| {
+            (
+                self.priority(p),
+                buddy.paths[p].unwrap_or(Duration::MAX),
+                p.direction_id,
+            )
+        };
+        let best: Option<Path> = buddy.paths.keys().min_by_key(|p| key(p)).copied();
+        let preferred: Option<Path> = match (buddy.preferred, best) {
+            (Some(current), Some(best)) if self.priority(&current) == self.priority(&best) => {
+                match (buddy.paths[&current], buddy.paths[&best]) {
+                    (Some(c), Some(b)) if b.as_secs_f64() < c.as_secs_f64() * RTT_SWITCH_RATIO => {
+                        Some(best)
+                    }
+                    (None, Some(_)) => Some(best),
+                    _ => Some(current),
+                }
+            }
+            (_, best) => best,
+        };
+        let mut paths: Vec<Path> = buddy.paths.keys().copied().collect();
+        paths.sort_by_key(|p| key(p));
+        if let Some(b) = self.buddies.get_mut(&id) {
+            b.preferred = preferred;
+        }
+        Routes {
+            id,
+            paths,
+            preferred,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(direction_id: usize, last: u8) -> Path {
+        Path {
+            direction_id,
+            ip: [10, direction_id as u8, 0, last].into(),
+        }
+    }
+
+    #[test]
+    fn failover() {
+        let mut r = Roster::new(vec![0, 0]);
+        let wg = path(0, 2);
+        let ovpn = path(1, 2);
+        assert_eq!(r.seen(7, wg)[0].preferred, Some(wg));
+        let routes = &r.seen(7, ovpn)[0];
+        assert_eq!(routes.paths.len(), 2);
+        assert_eq!(routes.preferred, Some(wg));
+
+        // Measured path beats unmeasured one, then only a clearly faster one wins
+        let ms = Duration::from_millis;
+        assert_eq!(r.rtt(ovpn, ms(40)).unwrap().preferred, Some(ovpn));
+        assert_eq!(r.rtt(wg, ms(35)).unwrap().preferred, Some(ovpn));
+        assert_eq!(r.rtt(wg, ms(10)).unwrap().preferred, Some(wg));
+
+        let routes = r.lost(wg).unwrap();
+        assert_eq!(routes.preferred, Some(ovpn));
+        assert!(routes.paths.contains(&wg));
+        let routes = r.lost(ovpn).unwrap();
+        assert_eq!(routes.preferred, None);
+        assert!(!r.is_known(7));
+        assert_eq!(r.lost(ovpn), None);
+    }
+
+    #[test]
+    fn priority() {
+        let mut r = Roster::new(vec![1, 0]);
+        let slow = path(1, 3);
+        let fast = path(0, 3);
+        r.seen(9, fast);
+        r.rtt(fast, Duration::from_millis(1));
+        assert_eq!(r.seen(9, slow)[0].preferred, Some(slow));
+
+        // Restarted buddy has new ID, the old one is left with the other path
+        let routes = r.seen(10, slow);
+        assert_eq!(routes[0].paths, [slow]);
+        assert_eq!(routes[1].id, 9);
+        assert_eq!(routes[1].preferred, Some(fast));
+        assert_eq!(r.id(&slow), Some(10));
+        assert_eq!(r.id(&fast), Some(9));
+    }
+}
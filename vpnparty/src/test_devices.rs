@@ -0,0 +1,30 @@
+// SPDX-FileCopyrightText: 2026 Vladyslav Tsilytskyi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Network devices for tests
+
+use std::net::IpAddr;
+
+use pcap::{Address, Device, DeviceFlags};
+
+/// Device with a single address
+pub fn device(name: &str, desc: Option<&str>, ip: impl Into<IpAddr>) -> Device {
+    Device {
+        name: name.to_string(),
+        desc: desc.map(|s| s.to_string()),
+        addresses: vec![Address {
+            addr: ip.into(),
+            netmask: None,
+            broadcast_addr: None,
+            dst_addr: None,
+        }],
+        flags: DeviceFlags::empty(),
+    }
+}
+
+/// Point-to-point device, which knows the address of the other end
+pub fn ptp_device(name: &str, ip: impl Into<IpAddr>, dst_addr: impl Into<IpAddr>) -> Device {
+    let mut d: Device = device(name, None, ip);
+    d.addresses[0].dst_addr = Some(dst_addr.into());
+    d
+}
@@ -0,0 +1,235 @@
+// SPDX-FileCopyrightText: 2026 Vladyslav Tsilytskyi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! UDP tunnel between vpnparty instances. Forwarded packets travel as payload of a regular
+//! datagram, so VPNs which drop injected packets still deliver them.
+//!
+//! Frame layout:
+//! ```text
+//! 0      1         2      3          5          7
+//! | type | version | hops | src port | dst port | payload | MAC |
+//! ```
+//! Hops count relays on the way, ports are big endian. Addresses of the datagram are the addresses of the rebuilt packet,
+//! same as with injected packets. With party secret frame ends with HMAC of sender IP and
+//! all preceding bytes. Frames aren't protected from replay, they only repeat a game announcement.
+
+use std::io;
+use std::net::{IpAddr, UdpSocket};
+use std::sync::mpsc::Sender;
+
+use crate::auth::{Secret, MAC_LEN};
+use crate::packet::{parse_udp4, parse_udp6, PacketError};
+use crate::rebroadcast::{self, Forwarded};
+use crate::udp::{craft_udp6_packet, craft_udp_packet};
+use crate::{debug, e, Vpacket};
+
+/// Doesn't overlap with Sup message types and legacy Sup
+const FRAME_TYPE: u8 = 0x20;
+/// Version 2 added hops to the header
+const TUNNEL_VERSION: u8 = 2;
+const HEADER_LEN: usize = 7;
+const IPV4_HEADER_LEN: usize = 20;
+const IPV6_HEADER_LEN: usize = 40;
+const UDP_HEADER_LEN: usize = 8;
+const HOP_LIMIT: u8 = 64;
+
+/// UDP datagram of the game without IP header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub hops: u8,
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    /// Frame of captured IPv4 or IPv6 packet, which was relayed given number of times
+    pub fn from_ip_packet(ip_packet: &[u8], hops: u8) -> Result<Frame, PacketError> {
+        let (offset, udp_len): (usize, usize) = if ip_packet.first().map(|b| b >> 4) == Some(6) {
+            (IPV6_HEADER_LEN, parse_udp6(ip_packet)?.udp_len)
+        } else {
+            let layout = parse_udp4(ip_packet)?;
+            (layout.ihl, layout.udp_len)
+        };
+        let udp: &[u8] = &ip_packet[offset..offset + udp_len];
+        Ok(Frame {
+            hops,
+            src_port: u16::from_be_bytes([udp[0], udp[1]]),
+            dst_port: u16::from_be_bytes([udp[2], udp[3]]),
+            payload: udp[UDP_HEADER_LEN..].to_vec(),
+        })
+    }
+
+    /// Encode frame sent from given address, sign it if party has a secret
+    pub fn encode_for(&self, sender_ip: IpAddr, secret: &Option<Secret>) -> Vec<u8> {
+        let mut buf: Vec<u8> = Vec::with_capacity(HEADER_LEN + self.payload.len() + MAC_LEN);
+        buf.push(FRAME_TYPE);
+        buf.push(TUNNEL_VERSION);
+        buf.push(self.hops);
+        buf.extend_from_slice(&self.src_port.to_be_bytes());
+        buf.extend_from_slice(&self.dst_port.to_be_bytes());
+        buf.extend_from_slice(&self.payload);
+        if let Some(s) = secret {
+            let tag: [u8; MAC_LEN] = s.sign(sender_ip, &buf);
+            buf.extend_from_slice(&tag);
+        }
+        buf
+    }
+
+    pub fn decode(buf: &[u8], sender_ip: IpAddr, secret: &Option<Secret>) -> Result<Frame, String> {
+        let mac_len: usize = if secret.is_some() { MAC_LEN } else { 0 };
+        if buf.len() < HEADER_LEN + mac_len {
+            return Err(format!("Tunnel frame is too short ({} bytes).", buf.len()));
+        }
+        if buf[0] != FRAME_TYPE {
+            return Err(format!("Unknown tunnel frame type {:#04x}.", buf[0]));
+        }
+        if buf[1] != TUNNEL_VERSION {
+            return Err(format!(
+                "Tunnel frame v{} is not supported, ours is v{}.",
+                buf[1], TUNNEL_VERSION
+            ));
+        }
+        let signed_len: usize = buf.len() - mac_len;
+        if let Some(s) = secret {
+            if !s.verify(sender_ip, &buf[..signed_len], &buf[signed_len..]) {
+                return Err("Tunnel frame has wrong MAC, different secret.".to_string());
+            }
+        }
+        Ok(Frame {
+            hops: buf[2],
+            src_port: u16::from_be_bytes([buf[3], buf[4]]),
+            dst_port: u16::from_be_bytes([buf[5], buf[6]]),
+            payload: buf[HEADER_LEN..signed_len].to_vec(),
+        })
+    }
+
+    /// Frame goes to one of the given ports, to any port if none are given
+    pub fn is_for(&self, ports: &[u16]) -> bool {
+        ports.is_empty() || ports.contains(&self.dst_port)
+    }
+
+    /// IP packet as if buddy had injected it into VPN, marked as forwarded
+    pub fn to_ip_packet(&self, src: IpAddr, dst: IpAddr) -> Result<Vec<u8>, String> {
+        let udp_len: usize = UDP_HEADER_LEN + self.payload.len();
+        let mut udp: Vec<u8> = Vec::with_capacity(udp_len);
+        udp.extend_from_slice(&self.src_port.to_be_bytes());
+        udp.extend_from_slice(&self.dst_port.to_be_bytes());
+        udp.extend_from_slice(&(udp_len as u16).to_be_bytes());
+        // Non-zero checksum is recomputed while crafting
+        udp.extend_from_slice(&[0xFF, 0xFF]);
+        udp.extend_from_slice(&self.payload);
+
+        match (src, dst) {
+            (IpAddr::V4(s), IpAddr::V4(d)) => {
+                let total_len: u16 = (IPV4_HEADER_LEN + udp_len) as u16;
+                let mut p: Vec<u8> = vec![0x45, 0x00];
+                p.extend_from_slice(&total_len.to_be_bytes());
+                p.extend_from_slice(&[0, 0, 0x40, 0x00, HOP_LIMIT, 17, 0, 0]);
+                p.extend_from_slice(&[0; 8]);
+                p.extend_from_slice(&udp);
+                craft_udp_packet(
+                    &p,
+                    &s.octets(),
+                    &d.octets(),
+                    None,
+                    Some(rebroadcast::mark(self.hops)),
+                )
+                .map_err(|e| e.to_string())
+            }
+            (IpAddr::V6(s), IpAddr::V6(d)) => {
+                let mut p: Vec<u8> = vec![0x60, 0, 0, 0];
+                p.extend_from_slice(&(udp_len as u16).to_be_bytes());
+                p.extend_from_slice(&[17, HOP_LIMIT]);
+                p.extend_from_slice(&[0; 32]);
+                p.extend_from_slice(&udp);
+                craft_udp6_packet(&p, &s.octets(), &d.octets(), None).map_err(|e| e.to_string())
+            }
+            _ => Err(format!("Can't send packet from {} to {}.", src, dst)),
+        }
+    }
+}
+
+/// Receive frames from buddies and turn them into packets of the direction.
+/// Only frames to the captured ports are accepted, same as injected packets.
+pub fn listen(
+    direction_id: usize,
+    socket: UdpSocket,
+    ttx: Sender<Vpacket>,
+    secret: Option<Secret>,
+    ports: Vec<u16>,
+) -> Result<(), String> {
+    let own_ip: IpAddr = e!(socket.local_addr()).ip();
+    let mut buf = vec![0u8; u16::MAX as usize];
+    loop {
+        let (len, remote) = match socket.recv_from(&mut buf) {
+            Ok(p) => p,
+            // Windows reports ICMP port unreachable of previous send here
+            Err(e) if e.kind() == io::ErrorKind::ConnectionReset => continue,
+            Err(e) => return Err(e.to_string()),
+        };
+        let frame: Result<(u8, Vec<u8>), String> = Frame::decode(&buf[..len], remote.ip(), &secret)
+            .and_then(|f| {
+                if !f.is_for(&ports) {
+                    return Err(format!("port {} is not captured", f.dst_port));
+                }
+                Ok((f.hops, f.to_ip_packet(remote.ip(), own_ip)?))
+            });
+        match frame {
+            Ok((hops, packet)) => e!(ttx.send(Vpacket::F(Forwarded {
+                direction_id,
+                packet,
+                tunneled: true,
+                hops,
+            }))),
+            Err(e) => debug!("Skipping tunnel frame from {}: {}", remote.ip(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let buddy: IpAddr = [10, 0, 0, 1].into();
+        let me: IpAddr = [10, 0, 0, 2].into();
+        let frame = Frame {
+            hops: 1,
+            src_port: 6112,
+            dst_port: 6112,
+            payload: vec![0xf7, 0x2f, 0x10, 0x00],
+        };
+        let plain: Vec<u8> = frame.encode_for(buddy, &None);
+        assert_eq!(Frame::decode(&plain, buddy, &None), Ok(frame.clone()));
+
+        let secret: Option<Secret> = Some(Secret::new("our party").unwrap());
+        let signed: Vec<u8> = frame.encode_for(buddy, &secret);
+        assert_eq!(Frame::decode(&signed, buddy, &secret), Ok(frame.clone()));
+        assert!(Frame::decode(&signed, me, &secret).is_err());
+        assert!(Frame::decode(&plain, buddy, &secret).is_err());
+
+        let mut old: Vec<u8> = plain.clone();
+        old[1] = 1;
+        assert!(Frame::decode(&old, buddy, &None).is_err());
+
+        assert!(frame.is_for(&[]));
+        assert!(frame.is_for(&[6112, 6113]));
+        assert!(!frame.is_for(&[4000]));
+
+        // Rebuilt packet carries the same datagram
+        let packet: Vec<u8> = frame.to_ip_packet(buddy, me).unwrap();
+        assert_eq!(packet[4..6], rebroadcast::mark(1).to_be_bytes());
+        assert_eq!(packet[12..20], [10, 0, 0, 1, 10, 0, 0, 2]);
+        assert_eq!(Frame::from_ip_packet(&packet, 1), Ok(frame.clone()));
+
+        let v6: Vec<u8> = frame
+            .to_ip_packet("fd00::1".parse().unwrap(), "fd00::2".parse().unwrap())
+            .unwrap();
+        assert_eq!(Frame::from_ip_packet(&v6, 1), Ok(frame.clone()));
+        assert!(frame
+            .to_ip_packet(buddy, "fd00::2".parse().unwrap())
+            .is_err());
+    }
+}
@@ -2,9 +2,13 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use crate::critical;
+use crate::packet::{parse_udp4, parse_udp6, PacketError, Udp4Layout, Udp6Layout};
+
+/// Hop limit of forwarded IPv6 packets. Link-local multicast is usually sent with 1.
+const IPV6_HOP_LIMIT: u8 = 64;
 
 fn rewrite_ip4_checksum(buf: &mut [u8]) -> Result<(), String> {
-    if buf.len() != 20 {
+    if !(20..=60).contains(&buf.len()) {
         return Err("Incorrect packet header length.".to_string());
     }
     buf[10] = 0u8;
@@ -20,11 +24,71 @@ fn calculate_ip4_checksum(buf: &[u8]) -> u16 {
         .chunks(2)
         .map(|c| ((c[0] as usize) << 8) + (c[1] as usize))
         .sum();
-    let carry: usize = sum >> 16;
-    let checksum: u16 = !(((sum & 0xFFFF) + carry) as u16);
+    let mut folded: usize = sum;
+    while folded >> 16 != 0 {
+        folded = (folded & 0xFFFF) + (folded >> 16);
+    }
+    let checksum: u16 = !(folded as u16);
     checksum
 }
 
+/// Sum of 16-bit big-endian words. Odd trailing byte is padded with zero.
+fn sum_be_words(buf: &[u8]) -> usize {
+    buf.chunks(2)
+        .map(|c| ((c[0] as usize) << 8) + (*c.get(1).unwrap_or(&0) as usize))
+        .sum()
+}
+
+/// UDP checksum over IPv4 pseudo-header and UDP segment (header + payload).
+/// Checksum field of the segment must be zeroed.
+fn calculate_udp4_checksum(src: &[u8; 4], dst: &[u8; 4], segment: &[u8]) -> u16 {
+    let mut sum: usize = sum_be_words(src) + sum_be_words(dst) + 17 + segment.len();
+    sum += sum_be_words(segment);
+    while sum >> 16 != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    let checksum: u16 = !(sum as u16);
+    // Zero means "no checksum" in IPv4, so computed zero is transmitted as all ones.
+    if checksum == 0 {
+        0xFFFF
+    } else {
+        checksum
+    }
+}
+
+/// Recompute UDP checksum of validated IPv4 packet.
+/// Zero checksum means that sender disabled it, so we keep it as is.
+fn rewrite_udp4_checksum(buf: &mut [u8], layout: &Udp4Layout) {
+    let u: usize = layout.ihl;
+    if buf[u + 6] == 0 && buf[u + 7] == 0 {
+        return;
+    }
+    let src: [u8; 4] = [buf[12], buf[13], buf[14], buf[15]];
+    let dst: [u8; 4] = [buf[16], buf[17], buf[18], buf[19]];
+    buf[u + 6] = 0u8;
+    buf[u + 7] = 0u8;
+    let checksum: u16 = calculate_udp4_checksum(&src, &dst, &buf[u..u + layout.udp_len]);
+    buf[u + 6] = (checksum >> 8) as u8;
+    buf[u + 7] = (checksum & 0xFF) as u8;
+}
+
+/// UDP checksum over IPv6 pseudo-header and UDP segment (header + payload).
+/// Checksum field of the segment must be zeroed.
+fn calculate_udp6_checksum(src: &[u8; 16], dst: &[u8; 16], segment: &[u8]) -> u16 {
+    let mut sum: usize = sum_be_words(src) + sum_be_words(dst) + 17 + segment.len();
+    sum += sum_be_words(segment);
+    while sum >> 16 != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    let checksum: u16 = !(sum as u16);
+    // Checksum is mandatory in IPv6, computed zero is transmitted as all ones.
+    if checksum == 0 {
+        0xFFFF
+    } else {
+        checksum
+    }
+}
+
 #[test]
 fn ip4_checksum() {
     #[rustfmt::skip]
@@ -37,14 +101,17 @@ fn ip4_checksum() {
     assert_eq!(given_checksum, expected_checksum);
 }
 
+/// Copy IPv4/UDP packet with new addresses, destination port and identification.
+/// Link layer padding is dropped, malformed packets are rejected.
 pub fn craft_udp_packet(
     given: &[u8],
     src: &[u8; 4],
     dst: &[u8; 4],
     dst_port: Option<u16>,
     id: Option<u16>,
-) -> Vec<u8> {
-    let mut no_ether_pktbuf: Vec<u8> = given.to_vec();
+) -> Result<Vec<u8>, PacketError> {
+    let layout: Udp4Layout = parse_udp4(given)?;
+    let mut no_ether_pktbuf: Vec<u8> = given[..layout.total_len].to_vec();
 
     // Rewrite Identification field if required
     if let Some(udpid) = id {
@@ -57,15 +124,161 @@ pub fn craft_udp_packet(
     no_ether_pktbuf[16..20].copy_from_slice(dst);
 
     if let Some(port) = dst_port {
-        no_ether_pktbuf[22] = (port >> 8) as u8;
-        no_ether_pktbuf[23] = (port & 0xFF) as u8;
+        no_ether_pktbuf[layout.ihl + 2] = (port >> 8) as u8;
+        no_ether_pktbuf[layout.ihl + 3] = (port & 0xFF) as u8;
     }
 
-    if rewrite_ip4_checksum(&mut no_ether_pktbuf[0..20]).is_err() {
+    if rewrite_ip4_checksum(&mut no_ether_pktbuf[0..layout.ihl]).is_err() {
         critical!("Should never happen! Checksum calculation error.");
     }
 
-    //TODO: UDP checksum (optional)
+    rewrite_udp4_checksum(&mut no_ether_pktbuf, &layout);
+
+    Ok(no_ether_pktbuf)
+}
+
+/// Copy IPv6/UDP packet with new addresses and destination port.
+/// Link layer padding is dropped, malformed packets are rejected.
+pub fn craft_udp6_packet(
+    given: &[u8],
+    src: &[u8; 16],
+    dst: &[u8; 16],
+    dst_port: Option<u16>,
+) -> Result<Vec<u8>, PacketError> {
+    let layout: Udp6Layout = parse_udp6(given)?;
+    let mut pktbuf: Vec<u8> = given[..layout.total_len].to_vec();
+
+    // Packet is routed through VPN now, it isn't link-local anymore
+    pktbuf[7] = std::cmp::max(pktbuf[7], IPV6_HOP_LIMIT);
+
+    // Rewrite source and destination IPs
+    pktbuf[8..24].copy_from_slice(src);
+    pktbuf[24..40].copy_from_slice(dst);
+
+    if let Some(port) = dst_port {
+        pktbuf[42] = (port >> 8) as u8;
+        pktbuf[43] = (port & 0xFF) as u8;
+    }
+
+    pktbuf[46] = 0u8;
+    pktbuf[47] = 0u8;
+    let checksum: u16 = calculate_udp6_checksum(src, dst, &pktbuf[40..40 + layout.udp_len]);
+    pktbuf[46] = (checksum >> 8) as u8;
+    pktbuf[47] = (checksum & 0xFF) as u8;
+
+    Ok(pktbuf)
+}
+
+/// Warcraft 3 broadcast from 192.168.0.10, UDP checksum 0x6a6c.
+#[rustfmt::skip]
+#[cfg(test)]
+const WC3_BROADCAST: [u8; 44] = [
+    0x45, 0x00, 0x00, 0x2c, 0x12, 0x34, 0x00, 0x00, 0x80, 0x11,
+    0x67, 0xdb, 0xc0, 0xa8, 0x00, 0x0a, 0xff, 0xff, 0xff, 0xff,
+    0x17, 0xe0, 0x17, 0xe0, 0x00, 0x18, 0x6a, 0x6c, 0xf7, 0x2f,
+    0x10, 0x00, 0x50, 0x58, 0x33, 0x57, 0x1a, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00,
+];
+
+/// Torchlight 2 broadcast from 192.168.0.10, odd payload length, UDP checksum 0xef9c.
+#[rustfmt::skip]
+#[cfg(test)]
+const TL2_BROADCAST: [u8; 35] = [
+    0x45, 0x00, 0x00, 0x23, 0x12, 0x34, 0x00, 0x00, 0x80, 0x11,
+    0x67, 0xe4, 0xc0, 0xa8, 0x00, 0x0a, 0xff, 0xff, 0xff, 0xff,
+    0x11, 0xc5, 0x11, 0xc5, 0x00, 0x0f, 0xef, 0x9c, 0xab, 0x84,
+    0x54, 0x72, 0x2c, 0x00, 0x00,
+];
+
+#[test]
+fn udp4_checksum() {
+    let src: [u8; 4] = [192, 168, 0, 10];
+    let dst: [u8; 4] = [255, 255, 255, 255];
+
+    let mut wc3: Vec<u8> = WC3_BROADCAST[20..].to_vec();
+    wc3[6] = 0;
+    wc3[7] = 0;
+    assert_eq!(calculate_udp4_checksum(&src, &dst, &wc3), 0x6a6c);
+
+    let mut tl2: Vec<u8> = TL2_BROADCAST[20..].to_vec();
+    tl2[6] = 0;
+    tl2[7] = 0;
+    assert_eq!(calculate_udp4_checksum(&src, &dst, &tl2), 0xef9c);
+}
+
+#[test]
+fn craft_udp_packet_checksums() {
+    let src: [u8; 4] = [10, 0, 0, 1];
+    let dst: [u8; 4] = [10, 0, 0, 2];
+
+    let wc3: Vec<u8> = craft_udp_packet(&WC3_BROADCAST, &src, &dst, None, None).unwrap();
+    assert_eq!(wc3[10..12], [0x14, 0x8b]);
+    assert_eq!(wc3[26..28], [0x17, 0x1c]);
+
+    // Ethernet padding must not affect the checksum.
+    let mut padded: Vec<u8> = TL2_BROADCAST.to_vec();
+    padded.extend_from_slice(&[0u8; 11]);
+    let tl2: Vec<u8> = craft_udp_packet(&padded, &src, &dst, None, None).unwrap();
+    assert_eq!(tl2.len(), TL2_BROADCAST.len());
+    assert_eq!(tl2[10..12], [0x14, 0x94]);
+    assert_eq!(tl2[26..28], [0x9c, 0x4c]);
+
+    // Destination port rewrite is covered by the checksum as well.
+    let mdns: Vec<u8> = craft_udp_packet(&WC3_BROADCAST, &src, &dst, Some(54927), None).unwrap();
+    let mut sum: usize = sum_be_words(&src) + sum_be_words(&dst) + 17 + 24;
+    sum += sum_be_words(&mdns[20..]);
+    while sum >> 16 != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    assert_eq!(sum, 0xFFFF);
+}
+
+#[test]
+fn craft_udp_packet_keeps_disabled_checksum() {
+    let mut given: Vec<u8> = WC3_BROADCAST.to_vec();
+    given[26] = 0;
+    given[27] = 0;
+    let crafted: Vec<u8> =
+        craft_udp_packet(&given, &[10, 0, 0, 1], &[10, 0, 0, 2], None, None).unwrap();
+    assert_eq!(crafted[26..28], [0x00, 0x00]);
+}
+
+#[test]
+fn craft_udp_packet_rejects_malformed() {
+    let truncated: Vec<u8> = WC3_BROADCAST[..30].to_vec();
+    assert!(craft_udp_packet(&truncated, &[10, 0, 0, 1], &[10, 0, 0, 2], None, None).is_err());
+}
+
+/// Torchlight 2 announcement from fe80::1 to ff02::1 with hop limit 1, UDP checksum 0xdf43.
+#[rustfmt::skip]
+#[cfg(test)]
+const TL2_IPV6_MULTICAST: [u8; 51] = [
+    0x60, 0x00, 0x00, 0x00, 0x00, 0x0b, 0x11, 0x01,
+    0xfe, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+    0xff, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+    0x11, 0xc5, 0x11, 0xc5, 0x00, 0x0b, 0xdf, 0x43,
+    0xab, 0x84, 0x54,
+];
+
+#[test]
+fn craft_udp6_packet_checksum() {
+    let src: [u8; 16] = [0xfd, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+    let dst: [u8; 16] = [0xfd, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2];
+
+    let mut given: Vec<u8> = TL2_IPV6_MULTICAST.to_vec();
+    given[46] = 0;
+    given[47] = 0;
+    let fe80: [u8; 16] = TL2_IPV6_MULTICAST[8..24].try_into().unwrap();
+    let ff02: [u8; 16] = TL2_IPV6_MULTICAST[24..40].try_into().unwrap();
+    assert_eq!(calculate_udp6_checksum(&fe80, &ff02, &given[40..]), 0xdf43);
+
+    let crafted: Vec<u8> = craft_udp6_packet(&TL2_IPV6_MULTICAST, &src, &dst, None).unwrap();
+    assert_eq!(crafted[7], 64);
+    assert_eq!(crafted[8..24], src);
+    assert_eq!(crafted[24..40], dst);
+    assert_eq!(crafted[46..48], [0xe2, 0xc4]);
 
-    no_ether_pktbuf
+    assert!(craft_udp6_packet(&WC3_BROADCAST, &src, &dst, None).is_err());
 }
@@ -1,76 +1,242 @@
 // SPDX-FileCopyrightText: 2026 Vladyslav Tsilytskyi
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use crate::{debug, e, error, Vpacket, SUP, SUP_LEN, SUP_REPLY};
-use std::io;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
-use std::sync::mpsc::Sender;
-
-/// Send Sup! packet to IP range 255.255.255.0
-fn udping(socket: &UdpSocket, ip: Ipv4Addr, port: u16) -> Result<(), String> {
-    let mut ip_oct: [u8; 4] = ip.octets();
-    let self_d = ip_oct[3];
-    for d in 0..255 {
-        if d == self_d {
-            continue;
+use crate::discovery::{Discovery, Mechanism};
+use crate::subnet::{IpNet, Ipv4Net};
+use crate::sup::{self, Settings};
+use crate::{debug, trace, warn};
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// Larger networks are swept only around own address
+const MAX_SWEEP_PREFIX: u8 = 20;
+
+/// Hosts to greet when no scan targets are given for the direction
+fn default_targets(vpnnet: IpNet, buddies: &[IpAddr]) -> Vec<IpNet> {
+    match vpnnet {
+        IpNet::V4(net) if net.prefix < MAX_SWEEP_PREFIX => {
+            let s = Ipv4Net {
+                addr: net.addr,
+                prefix: MAX_SWEEP_PREFIX,
+            };
+            debug!("{} network is too large, sweeping only {}.", net, s);
+            vec![IpNet::V4(s)]
+        }
+        IpNet::V4(_) => vec![vpnnet],
+        // IPv6 networks are too large to sweep, so only known buddies are greeted
+        IpNet::V6(_) => {
+            if buddies.is_empty() {
+                debug!("No IPv6 buddies to greet, waiting for their Sup.");
+            }
+            buddies.iter().map(|b| IpNet::host(*b)).collect()
         }
-        ip_oct[3] = d;
-        let dst_socket_addr = SocketAddr::from((ip_oct, port));
-        e!(socket.send_to(&SUP, dst_socket_addr));
     }
-    Ok(())
 }
 
-pub fn run_udping(
-    direction_id: usize,
-    btx: Sender<Vpacket>,
-    src_ip: Ipv4Addr,
-    udping_port: u16,
-) -> Result<(), String> {
-    let socket_addr: SocketAddr = SocketAddr::new(IpAddr::V4(src_ip), udping_port);
-    let udp_socket: UdpSocket = e!(UdpSocket::bind(socket_addr));
-
-    e!(udping(&udp_socket, src_ip, udping_port));
-
-    let mut buf = [0; 100];
-
-    loop {
-        let (len, remote_addr) = match udp_socket.recv_from(&mut buf) {
-            Ok(p) => p,
-            Err(e) => {
-                if e.kind() == io::ErrorKind::ConnectionReset {
-                    // Happens :(
-                    continue;
-                }
-                error!("Error while receiving UDP packet: {}", e);
-                continue;
-            }
-        };
+/// Spreads packets evenly, so sweep of a large network doesn't trip VPN rate limiter
+#[derive(Debug)]
+struct Pacer {
+    interval: Duration,
+    next: Instant,
+}
 
-        let buddy_ip: IpAddr = remote_addr.ip();
+impl Pacer {
+    /// Zero rate means no limit
+    fn new(packets_per_second: u32, now: Instant) -> Pacer {
+        let interval: Duration = if packets_per_second == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs(1) / packets_per_second
+        };
+        Pacer {
+            interval,
+            next: now,
+        }
+    }
 
-        if buddy_ip == src_ip {
-            debug!("Sup from {}!", src_ip);
-            continue;
+    /// Time to greet next host
+    fn take(&mut self, now: Instant) -> bool {
+        if now < self.next {
+            return false;
+        }
+        // Time lost while handling incoming packets is not made up with a burst
+        if now >= self.next + self.interval {
+            self.next = now;
         }
+        self.next += self.interval;
+        true
+    }
+}
 
-        // trace!("UUU {:?}", &buf[..len]);
+/// Greeting of every scan target, a few hosts at a time
+struct Sweep {
+    hosts: Box<dyn Iterator<Item = IpAddr> + Send>,
+    pacer: Pacer,
+    started: Instant,
+    greeted: u64,
+    failed: u64,
+    last_error: Option<String>,
+}
 
-        if len == SUP_LEN && buf[0..SUP_LEN] == SUP {
-            // Greetings to the newcommer.
-            if let Err(e) = udp_socket.send_to(&SUP_REPLY, remote_addr) {
-                error!("Greeting the {} buddy failed: {}", buddy_ip, e);
-            }
+impl Sweep {
+    fn new(targets: Vec<IpNet>, own_ip: IpAddr, settings: &Settings, now: Instant) -> Sweep {
+        let total: u64 = targets
+            .iter()
+            .fold(0u64, |sum, t| sum.saturating_add(t.host_count()));
+        let names: Vec<String> = targets.iter().map(|t| t.to_string()).collect();
+        debug!("Sweeping {} hosts of {}.", total, names.join(", "));
+        let hosts = targets
+            .into_iter()
+            .flat_map(|t| t.hosts())
+            .filter(move |ip| *ip != own_ip);
+        Sweep {
+            hosts: Box::new(hosts),
+            pacer: Pacer::new(settings.pps, now),
+            started: now,
+            greeted: 0,
+            failed: 0,
+            last_error: None,
         }
+    }
 
-        match buddy_ip {
-            IpAddr::V4(remote_ipv4_addr) => {
-                e!(btx.send(Vpacket::U((direction_id, remote_ipv4_addr))))
-            }
-            IpAddr::V6(remote_ipv6_addr) => {
-                error!("Received packet from IPv6 address {}", remote_ipv6_addr)
+    /// Greet hosts, which are due. Returns false when every host was greeted.
+    fn step(&mut self, socket: &UdpSocket, port: u16, settings: &Settings, now: Instant) -> bool {
+        while self.pacer.take(now) {
+            let Some(ip) = self.hosts.next() else {
+                return false;
+            };
+            // Single unreachable host must not stop discovery
+            match sup::send_hello(socket, SocketAddr::new(ip, port), settings) {
+                Ok(()) => self.greeted += 1,
+                Err(e) => {
+                    trace!("Can't greet {}: {}", ip, e);
+                    self.failed += 1;
+                    self.last_error = Some(e);
+                }
             }
+        }
+        true
+    }
+
+    fn report(&self, vpnnet: IpNet, now: Instant) {
+        let took: f32 = now.saturating_duration_since(self.started).as_secs_f32();
+        match &self.last_error {
+            None => debug!(
+                "UDP discovery of {} greeted {} hosts in {:.1}s.",
+                vpnnet, self.greeted, took
+            ),
+            Some(e) => warn!(
+                "UDP discovery of {} greeted {} hosts in {:.1}s, {} more failed. Last error: {}",
+                vpnnet, self.greeted, took, self.failed, e
+            ),
+        }
+    }
+}
+
+/// Peer discovery via greeting every host of scan targets
+pub struct Udping {
+    vpnnet: IpNet,
+    targets: Vec<IpNet>,
+    port: u16,
+    sweep: Option<Sweep>,
+}
+
+impl Udping {
+    /// Own VPN network is swept, unless scan targets are given
+    pub fn new(vpnnet: IpNet, buddies: &[IpAddr], scan: Vec<IpNet>, port: u16) -> Udping {
+        let targets: Vec<IpNet> = if scan.is_empty() {
+            default_targets(vpnnet, buddies)
+        } else {
+            scan
         };
+        Udping {
+            vpnnet,
+            targets,
+            port,
+            sweep: None,
+        }
+    }
+}
+
+impl Discovery for Udping {
+    fn mechanism(&self) -> Mechanism {
+        Mechanism::Udping
+    }
+
+    fn start(&mut self) -> Result<UdpSocket, String> {
+        let socket_addr: SocketAddr = SocketAddr::new(self.vpnnet.addr(), self.port);
+        UdpSocket::bind(socket_addr).map_err(|e| format!("Can't bind {}: {}", socket_addr, e))
+    }
+
+    fn greet(
+        &mut self,
+        _socket: &UdpSocket,
+        settings: &Settings,
+        now: Instant,
+    ) -> Result<(), String> {
+        // Slow sweep of a large network may still be running
+        if self.sweep.is_none() {
+            self.sweep = Some(Sweep::new(
+                self.targets.clone(),
+                self.vpnnet.addr(),
+                settings,
+                now,
+            ));
+        }
+        Ok(())
+    }
+
+    fn tick(&mut self, socket: &UdpSocket, settings: &Settings, now: Instant) -> Option<Instant> {
+        let sweep: &mut Sweep = self.sweep.as_mut()?;
+        if sweep.step(socket, self.port, settings, now) {
+            return Some(sweep.pacer.next);
+        }
+        sweep.report(self.vpnnet, now);
+        self.sweep = None;
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pacing() {
+        let t0 = Instant::now();
+        let mut p = Pacer::new(50, t0);
+        assert_eq!(p.interval, Duration::from_millis(20));
+        assert!(p.take(t0));
+        assert!(!p.take(t0));
+        assert_eq!(p.next, t0 + Duration::from_millis(20));
+        assert!(p.take(t0 + Duration::from_millis(20)));
+
+        // No burst after a long pause
+        let later = t0 + Duration::from_secs(1);
+        assert!(p.take(later));
+        assert!(!p.take(later));
+
+        let mut unlimited = Pacer::new(0, t0);
+        assert!((0..1000).all(|_| unlimited.take(t0)));
+    }
+
+    #[test]
+    fn targets() {
+        let own: IpAddr = [10, 0, 0, 1].into();
+        let net: IpNet = "10.0.0.1/8".parse().unwrap();
+        assert_eq!(
+            default_targets(net, &[]),
+            ["10.0.0.1/20".parse::<IpNet>().unwrap()]
+        );
+        let buddy: IpAddr = "fd00::2".parse().unwrap();
+        assert_eq!(
+            default_targets("fd00::1/64".parse().unwrap(), &[buddy]),
+            [IpNet::host(buddy)]
+        );
+
+        let t0 = Instant::now();
+        let scan: Vec<IpNet> = vec!["10.0.0.0/30".parse().unwrap()];
+        let s = Sweep::new(scan, own, &Settings::default(), t0);
+        assert_eq!(s.hosts.collect::<Vec<_>>(), [IpAddr::from([10, 0, 0, 2])]);
     }
-    // TODO: close UDP socket
 }